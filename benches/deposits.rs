@@ -0,0 +1,26 @@
+//! Times a deposit-heavy workload: one million deposits spread over a thousand clients, with one in
+//! a thousand disputed. Run with `cargo bench --bench deposits`.
+
+use std::{fmt::Write, time::Instant};
+
+use payments_engine::Engine;
+
+const DEPOSITS: u32 = 1_000_000;
+const CLIENTS: u32 = 1_000;
+
+fn main() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 0..DEPOSITS {
+        writeln!(input, "deposit,{},{tx},1.2345", tx % CLIENTS).expect("writing to a String can't fail");
+    }
+    for tx in (0..DEPOSITS).step_by(1_000) {
+        writeln!(input, "dispute,{},{tx},", tx % CLIENTS).expect("writing to a String can't fail");
+    }
+
+    let start = Instant::now();
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(input.as_bytes())
+        .expect("benchmark input is valid");
+    println!("{DEPOSITS} deposits in {:?}: {engine:?}", start.elapsed());
+}
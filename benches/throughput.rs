@@ -0,0 +1,90 @@
+//! Criterion benchmarks for the processing loop and the output writer, over one million mixed
+//! transactions spread across ten thousand clients. The input comes from a seeded generator, so
+//! runs are comparable. Run with `cargo bench --bench throughput`.
+
+use std::{fmt::Write, hint::black_box};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use payments_engine::Engine;
+
+const TRANSACTIONS: u32 = 1_000_000;
+const CLIENTS: u32 = 10_000;
+const SEED: u64 = 0x5eed;
+
+// xorshift64*, which is plenty for picking transaction kinds and amounts.
+struct Rng(u64);
+
+impl Rng {
+    const fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: u32) -> u32 {
+        u32::try_from(self.next() % u64::from(bound)).expect("value is below a u32 bound")
+    }
+}
+
+// Mostly deposits and withdrawals, with the dispute steps referring back to each client's latest
+// deposit so that most of them apply.
+fn generate_input() -> String {
+    let mut rng = Rng(SEED);
+    let mut last_deposit = vec![None; CLIENTS as usize];
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 0..TRANSACTIONS {
+        let client = rng.below(CLIENTS);
+        let amount = format!("{}.{:04}", rng.below(1_000), rng.below(10_000));
+        let deposit = last_deposit[client as usize];
+        let row = match (rng.below(100), deposit) {
+            (0..=59, _) | (_, None) => {
+                last_deposit[client as usize] = Some(tx);
+                writeln!(input, "deposit,{client},{tx},{amount}")
+            }
+            (60..=89, _) => writeln!(input, "withdrawal,{client},{tx},{amount}"),
+            (90..=95, Some(deposit)) => writeln!(input, "dispute,{client},{deposit},"),
+            (96..=98, Some(deposit)) => writeln!(input, "resolve,{client},{deposit},"),
+            (_, Some(deposit)) => writeln!(input, "chargeback,{client},{deposit},"),
+        };
+        row.expect("writing to a String can't fail");
+    }
+    input
+}
+
+fn apply_transactions(c: &mut Criterion) {
+    let input = generate_input();
+    let mut group = c.benchmark_group("apply_transactions");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(u64::from(TRANSACTIONS)));
+    group.bench_function("mixed", |b| {
+        b.iter(|| {
+            let mut engine = Engine::new();
+            engine
+                .apply_transactions(black_box(input.as_bytes()))
+                .expect("benchmark input is valid");
+            engine
+        });
+    });
+    group.finish();
+}
+
+fn write_accounts(c: &mut Criterion) {
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(generate_input().as_bytes())
+        .expect("benchmark input is valid");
+    let mut group = c.benchmark_group("write_accounts");
+    group.throughput(Throughput::Elements(u64::from(CLIENTS)));
+    group.bench_function("mixed", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output).expect("writing to a Vec can't fail");
+            output
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, apply_transactions, write_accounts);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+use std::{fmt, io::Write};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{ClientId, InputTransaction, TransactionId, TransactionIds};
+
+/// Optional destination for the JSON Lines audit stream.
+///
+/// Cloning an engine deliberately doesn't clone its sink: a branched engine is for hypothetical
+/// transactions, which shouldn't end up in the durable audit log.
+#[derive(Default)]
+pub struct AuditSink(Option<Box<dyn Write + Send>>);
+
+#[derive(Serialize)]
+struct AuditEvent {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    client: ClientId,
+    tx: TransactionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<Decimal>,
+}
+
+impl AuditSink {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self(Some(Box::new(writer)))
+    }
+
+    /// Writes one JSON object line for an applied transaction. Does nothing without a sink.
+    pub fn record(&mut self, transaction: InputTransaction) -> Result<(), serde_json::Error> {
+        let Some(writer) = self.0.as_mut() else {
+            return Ok(());
+        };
+        let TransactionIds { client, tx } = transaction.ids();
+        let event = AuditEvent {
+            kind: transaction.kind(),
+            client,
+            tx,
+            amount: transaction.amount(),
+        };
+        serde_json::to_writer(&mut *writer, &event)?;
+        writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+        writer.flush().map_err(serde_json::Error::io)
+    }
+}
+
+impl Clone for AuditSink {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+impl fmt::Debug for AuditSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AuditSink").field(&self.0.is_some()).finish()
+    }
+}
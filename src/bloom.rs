@@ -0,0 +1,58 @@
+use crate::TransactionId;
+
+/// Fixed-size probabilistic set of transaction ids. Lookups never miss an inserted id, but may
+/// report an id that was never inserted, at roughly the false positive rate it was sized for.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Sizes the filter for `expected_ids` insertions at `false_positive_rate`, using the usual
+    /// `m = -n ln p / ln² 2` bits and `k = m / n ln 2` hash functions.
+    // The sizes are estimates, so losing precision or truncating in the float conversions is fine.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn new(expected_ids: usize, false_positive_rate: f64) -> Self {
+        let expected_ids = expected_ids.max(1) as f64;
+        let false_positive_rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let bits = (-expected_ids * false_positive_rate.ln() / core::f64::consts::LN_2.powi(2)).ceil();
+        let words = ((bits / 64.0).ceil() as usize).max(1);
+        let hashes = ((words * 64) as f64 / expected_ids * core::f64::consts::LN_2).round() as u32;
+        Self {
+            bits: vec![0; words],
+            hashes: hashes.clamp(1, 16),
+        }
+    }
+
+    pub fn insert(&mut self, tx: TransactionId) {
+        for bit in self.bit_indexes(tx) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, tx: TransactionId) -> bool {
+        self.bit_indexes(tx)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // Double hashing: the i-th index is `h1 + i * h2`, with both halves taken from one mixed hash.
+    #[allow(clippy::cast_possible_truncation)]
+    fn bit_indexes(&self, tx: TransactionId) -> impl Iterator<Item = usize> {
+        let hash = splitmix64(u64::from(tx));
+        let (first, second) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let len = (self.bits.len() * 64) as u64;
+        (0..u64::from(self.hashes)).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % len) as usize)
+    }
+}
+
+const fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
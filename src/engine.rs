@@ -0,0 +1,5643 @@
+#[cfg(feature = "btree")]
+use std::collections::{btree_map::Entry, BTreeMap as AccountMap};
+#[cfg(not(feature = "btree"))]
+use std::collections::{hash_map::Entry, HashMap as AccountMap};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    mem,
+    num::NonZeroUsize,
+    ops::{Deref, Range, RangeInclusive},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use csv::{QuoteStyle, Trim};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{info_span, warn};
+
+use crate::{
+    audit::AuditSink,
+    bloom::BloomFilter,
+    ledger::{Account, AccountPolicy, Checkpoint, Deposit, DisputeHoldBasis, Transaction, TransactionState},
+    ClientId, LockReason, ProcessingSummary, TransactionId, TransactionOutcome,
+};
+
+// Number of decimal places balances are rounded to when written out, unless configured per column.
+const OUTPUT_SCALE: u32 = 4;
+// Columns every input header row must have, and the optional ones `RawInputTransaction` reads.
+// The amount is either one column or, in split mode, a whole and a fractional part.
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+const AMOUNT_COLUMNS: [&str; 1] = ["amount"];
+const SPLIT_AMOUNT_COLUMNS: [&str; 2] = ["amount_whole", "amount_frac"];
+const OPTIONAL_COLUMNS: [&str; 4] = ["currency", "description", "direction", "timestamp"];
+// FNV-1a parameters for the input checksum. A fixed algorithm rather than `DefaultHasher`, so
+// checksums stay comparable across builds.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+// Bloom dedup sizing unless configured: ten million ids at a 1 in 10,000 false positive rate,
+// about 24 MB.
+const DEFAULT_BLOOM_SIZING: (usize, f64) = (10_000_000, 0.0001);
+// Largest exponent accepted in scientific notation amounts. Anything bigger can't be a `Decimal`.
+const MAX_SCIENTIFIC_EXPONENT: u32 = 28;
+
+#[derive(Default, Clone)]
+pub struct Engine {
+    accounts: AccountMap<ClientId, Account>,
+    transaction_ids_processed: HashSet<TransactionId>,
+    // Replaces `transaction_ids_processed` with bloom dedup, created on the first claimed id.
+    bloom_ids: Option<BloomFilter>,
+    // Set on sharded workers, whose transaction ids the reader thread claims across all shards.
+    ids_claimed_upstream: bool,
+    // Clients in the order they first appeared, for `OutputOrder::FirstSeen`.
+    client_order: Vec<ClientId>,
+    // The most recently applied transactions, newest last, bounded by `EngineConfig::undo_depth`.
+    undo_log: VecDeque<UndoEntry>,
+    // Account states as of the last `Engine::take_deltas`.
+    delta_baseline: HashMap<ClientId, AccountSnapshot>,
+    // Count and sum of each client's withdrawals applied in the current batch, for the velocity
+    // limit.
+    withdrawal_velocity: HashMap<ClientId, (usize, Decimal)>,
+    // Dispute steps applied within the coalescing window, and how many transactions have been
+    // processed, which is what the window is measured in.
+    recent_dispute_steps: Vec<RecentDisputeStep>,
+    processed_count: u64,
+    // When each open dispute was raised, and the latest row timestamp seen, for auto-resolving
+    // stale disputes. Only tracked with auto-resolve enabled.
+    dispute_opened_at: HashMap<(ClientId, TransactionId), u64>,
+    latest_timestamp: Option<u64>,
+    // Largest balance magnitude each client has reached, which bounds how far the stored and
+    // computed totals can drift apart through rounding.
+    #[cfg(debug_assertions)]
+    balance_peaks: HashMap<ClientId, Decimal>,
+    // Running hash chained over every applied transaction, see `Engine::input_checksum`.
+    input_checksum: u64,
+    config: EngineConfig,
+    summary: ProcessingSummary,
+    audit: AuditSink,
+}
+
+// Each flag is an independent opt-in, not a state machine in disguise.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Default, Clone)]
+struct EngineConfig {
+    // When set, the written `total` is the sum of the rounded `available` and `held` columns, so the printed
+    // columns always add up. Otherwise `total` is rounded independently from the exact sum.
+    reconcile_totals: bool,
+    total_source: TotalSource,
+    // Surface dispute lifecycle steps from the wrong state as counted errors instead of silently
+    // ignoring them.
+    strict_state_transitions: bool,
+    account_policy: AccountPolicy,
+    output_order: OutputOrder,
+    // Amounts with more decimal places than this are rejected when parsed rather than silently
+    // rounded on output.
+    max_input_scale: Option<u32>,
+    per_transaction_spans: bool,
+    // Measure time spent parsing and applying rows into the summary.
+    timing: bool,
+    // Accept amounts in scientific notation such as `1.5e2`.
+    lenient_amount_parsing: bool,
+    // Read amounts from `amount_whole` and `amount_frac` instead of `amount`.
+    split_amount_columns: bool,
+    // With lenient parsing, read a comma as the decimal separator, as in `1,50`.
+    decimal_comma: bool,
+    // How many applied transactions `Engine::undo_last` can take back. Zero disables the log.
+    undo_depth: usize,
+    // Detect duplicate ids with a bloom filter sized as `bloom_sizing` instead of an exact set.
+    bloom_dedup: bool,
+    // Expected ids and false positive rate. `None` means `DEFAULT_BLOOM_SIZING`.
+    bloom_sizing: Option<(usize, f64)>,
+    // Hold back disputes of unseen transactions until the end of their batch.
+    dispute_buffering: bool,
+    // Lines starting with this byte are skipped as comments.
+    comment_char: Option<u8>,
+    // Reject inputs whose header row has unexpected or duplicate columns instead of only warning.
+    strict_headers: bool,
+    missing_amount_policy: MissingAmountPolicy,
+    // Input deposits with a negative amount are applied as withdrawals of the absolute amount.
+    negative_deposit_as_withdrawal: bool,
+    zero_display: ZeroDisplay,
+    column_precision: ColumnPrecision,
+    // Identical dispute steps repeated within this many transactions are coalesced. Zero disables
+    // coalescing.
+    dispute_coalesce_window: usize,
+    // Disputes open longer than this, by row timestamps, are resolved when the engine is finalized.
+    dispute_auto_resolve: Option<Duration>,
+    // Negative balances at least this close to zero are set to exactly zero after each transaction.
+    epsilon_clamp: Option<Decimal>,
+    suspicious_thresholds: SuspiciousPatternThresholds,
+    output_quoting: QuoteStyle,
+    // Whitespace trimming of input headers and fields. `None` means `Trim::All`.
+    input_trim: Option<Trim>,
+    total_mismatch_policy: TotalMismatchPolicy,
+    // Accept seeded accounts with held funds, which nothing can release since the disputes
+    // holding them aren't seeded.
+    seeded_held: bool,
+    // Most accounts transactions may open.
+    max_clients: Option<usize>,
+    // Most withdrawals, by count and by total amount, a client may make in one batch.
+    withdrawal_velocity_limit: Option<(usize, Decimal)>,
+    // Lock accounts that hit the withdrawal velocity limit.
+    freeze_on_velocity_limit: bool,
+    // Client ids kept for system accounts, which input transactions may not touch.
+    reserved_clients: Option<RangeInclusive<ClientId>>,
+    // Flush the output every this many account rows, rather than only once at the end.
+    flush_every: Option<NonZeroUsize>,
+    // Count resolves and chargebacks arriving before their deposit was disputed, to debug reordered
+    // feeds.
+    track_lifecycle_order: bool,
+    // Disputes must be resolved or charged back by the end of each input.
+    require_disputes_closed: bool,
+}
+
+/// Order accounts are written in by [`Engine::write_accounts`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputOrder {
+    /// Whatever order the accounts are stored in. Cheapest, but not stable between runs unless the
+    /// `btree` feature is enabled, which stores them by client id.
+    #[default]
+    Unspecified,
+    /// Ascending client id.
+    Sorted,
+    /// The order each client first appeared in the input.
+    FirstSeen,
+}
+
+/// What to do with a deposit, withdrawal or adjustment row that has a blank `amount`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MissingAmountPolicy {
+    /// Reject the row as [`EngineError::InputValidation`].
+    #[default]
+    Reject,
+    /// Parse the amount as zero. Zero deposits and withdrawals are then skipped as invalid amounts.
+    TreatAsZero,
+}
+
+/// How [`Engine::write_accounts`] prints balances, zeros in particular. A negative zero is always
+/// printed without its sign.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroDisplay {
+    /// Trailing zeros are trimmed, so zero prints as `0` and `1.5000` as `1.5`.
+    #[default]
+    Normalized,
+    /// Like `Normalized`, except zero prints as `0.0000`.
+    FixedZero,
+    /// Every balance, zero included, prints with exactly its column's number of decimal places,
+    /// four by default.
+    FixedScale,
+}
+
+/// Decimal places [`Engine::write_accounts`] rounds each balance column to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnPrecision {
+    pub available: u32,
+    pub held: u32,
+    pub total: u32,
+}
+
+impl Default for ColumnPrecision {
+    fn default() -> Self {
+        Self {
+            available: OUTPUT_SCALE,
+            held: OUTPUT_SCALE,
+            total: OUTPUT_SCALE,
+        }
+    }
+}
+
+/// Byte offsets of each field in a line of [`Engine::apply_transactions_fixed_width`] input.
+///
+/// Fields are trimmed, and an `amount` that is blank or past the end of a short line is missing,
+/// as for dispute rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedWidthLayout {
+    pub tx_type: Range<usize>,
+    pub client: Range<usize>,
+    pub tx: Range<usize>,
+    pub amount: Range<usize>,
+}
+
+/// When [`Engine::suspicious_patterns`] flags a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspiciousPatternThresholds {
+    /// Most transaction ids a withdrawal may come after the deposit it empties for the pair to
+    /// count as a cycle. Ids stand in for time, since rows carry no timestamps.
+    pub max_tx_gap: u32,
+    /// Smallest deposit worth flagging.
+    pub min_amount: Decimal,
+    /// Cycles a client needs before it's flagged.
+    pub min_cycles: usize,
+}
+
+impl Default for SuspiciousPatternThresholds {
+    fn default() -> Self {
+        Self {
+            max_tx_gap: 10,
+            min_amount: Decimal::ZERO,
+            min_cycles: 1,
+        }
+    }
+}
+
+/// A client whose activity looks like wash trading, from [`Engine::suspicious_patterns`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuspiciousPattern {
+    /// Deposits withdrawn in full soon after they arrived, as `(deposit, withdrawal)` id pairs.
+    RapidWithdrawal {
+        client: ClientId,
+        cycles: Vec<(TransactionId, TransactionId)>,
+    },
+}
+
+/// Where [`Engine::write_accounts`] takes each account's `total` from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TotalSource {
+    /// `available + held`, as they stand.
+    #[default]
+    Computed,
+    /// A running total kept from each transaction's net effect on the account. It only differs
+    /// from the computed total when balances were changed outside a transaction, as by
+    /// [`Engine::with_epsilon_clamp`].
+    Stored,
+}
+
+/// What [`Engine::seed_from_accounts_csv`] does with a row whose `total` isn't `available + held`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TotalMismatchPolicy {
+    /// Fail with [`EngineError::InputValidation`], since the seed is probably corrupt.
+    #[default]
+    Reject,
+    /// Warn and keep `available` and `held`, so the total becomes their sum.
+    Recompute,
+    /// Warn and keep `total` and `held`, deriving `available` as their difference.
+    TrustTotal,
+}
+
+/// Read-only view of an [`Engine`] once the input stream is done.
+///
+/// [`Engine::finalize`] consumes the engine, so applying further transactions is rejected by the
+/// type system rather than at runtime. Read methods such as [`Engine::write_accounts`] stay
+/// available through `Deref`.
+///
+/// ```compile_fail
+/// let mut engine = payments_engine::Engine::new();
+/// let finalized = engine.finalize();
+/// engine.apply_transactions("type,client,tx,amount\n".as_bytes());
+/// ```
+#[derive(Clone)]
+pub struct Finalized {
+    engine: Engine,
+}
+
+/// A single parsed input row.
+#[derive(Debug, Clone, Copy)]
+pub enum InputTransaction {
+    Deposit(TransactionIds, Decimal),
+    Withdrawal(TransactionIds, Decimal),
+    Dispute(TransactionIds),
+    Resolve(TransactionIds),
+    Chargeback(TransactionIds),
+    Adjustment(TransactionIds, Decimal),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionIds {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+
+/// Point-in-time copy of one account's balances, from [`Engine::account`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountSnapshot {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// Gross deposits ever applied, unaffected by disputes or chargebacks.
+    pub lifetime_deposited: Decimal,
+    /// Gross withdrawals ever applied.
+    pub lifetime_withdrawn: Decimal,
+    /// The balance set by [`Engine::set_opening_balance`], if any.
+    pub opening_balance: Option<Decimal>,
+}
+
+/// An input row that wasn't applied, from [`Engine::apply_transactions_collecting`].
+#[derive(Debug, Clone)]
+pub struct RowError {
+    /// Line of the row in the input, counting the header as line 1. Zero if unknown.
+    pub line: u64,
+    /// Why the row wasn't applied.
+    pub reason: String,
+    /// The row's fields joined with commas, after trimming. Empty if the row couldn't be read.
+    pub row: String,
+    /// The parsed transaction, or `None` for a malformed row.
+    pub transaction: Option<InputTransaction>,
+    // The row's currency and description, so a retry is checked against the same currency.
+    metadata: RowMetadata,
+}
+
+impl RowError {
+    fn malformed(error: &EngineError, record: Option<&csv::StringRecord>) -> Self {
+        let position = match (record, error) {
+            (Some(record), _) => record.position(),
+            (None, EngineError::Csv(error)) => error.position(),
+            (None, _) => None,
+        };
+        Self {
+            line: position.map_or(0, csv::Position::line),
+            reason: error.to_string(),
+            row: record.map(row_text).unwrap_or_default(),
+            transaction: None,
+            metadata: RowMetadata::default(),
+        }
+    }
+
+    fn skipped(
+        error: TransactionError,
+        transaction: InputTransaction,
+        metadata: &RowMetadata,
+        record: &csv::StringRecord,
+    ) -> Self {
+        Self {
+            line: record.position().map_or(0, csv::Position::line),
+            reason: error.to_string(),
+            row: row_text(record),
+            transaction: Some(transaction),
+            metadata: metadata.clone(),
+        }
+    }
+}
+
+fn row_text(record: &csv::StringRecord) -> String {
+    record.iter().collect::<Vec<_>>().join(",")
+}
+
+/// Totals across every account, from [`Engine::aggregate_stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AggregateStats {
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub total_available: Decimal,
+    pub total_held: Decimal,
+    pub system_total: Decimal,
+    /// Accounts whose total balance is negative.
+    pub negative_accounts: usize,
+    /// Deposits still disputed, across all accounts.
+    pub open_disputes: usize,
+}
+
+/// How one account changed between two calls to [`Engine::take_deltas`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountDelta {
+    pub available: Decimal,
+    pub held: Decimal,
+    /// The new lock state, if it changed.
+    pub locked: Option<bool>,
+}
+
+/// Kind of transaction in an account's history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKind {
+    Deposit,
+    Withdrawal,
+    Adjustment,
+}
+
+/// One applied transaction in an account's history, from [`Engine::transaction_history`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub tx: TransactionId,
+    pub kind: HistoryKind,
+    pub amount: Decimal,
+    /// The row's `description` column, if it had one.
+    pub description: Option<String>,
+    /// Where a deposit is in the dispute lifecycle. `None` for withdrawals and adjustments, which
+    /// can't be disputed.
+    pub state: Option<PublicTxState>,
+}
+
+/// Dispute lifecycle state of a deposit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicTxState {
+    Normal,
+    Disputed,
+    /// The dispute was resolved. The deposit can't be disputed again.
+    Resolved,
+    ChargedBack,
+}
+
+impl From<TransactionState> for PublicTxState {
+    fn from(state: TransactionState) -> Self {
+        match state {
+            TransactionState::Normal => Self::Normal,
+            TransactionState::Disputed => Self::Disputed,
+            TransactionState::Resolved => Self::Resolved,
+            TransactionState::ChargedBack => Self::ChargedBack,
+        }
+    }
+}
+
+/// Activity totals for one client, from [`Engine::client_stats`]. Only transactions that were
+/// applied are counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStats {
+    pub deposit_count: usize,
+    pub withdrawal_count: usize,
+    /// Deposits that have been disputed, whatever happened to the dispute afterwards.
+    pub dispute_count: usize,
+    /// Saturates at `Decimal::MAX`, like `total_withdrawn`.
+    pub total_deposited: Decimal,
+    pub total_withdrawn: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawInputTransaction {
+    #[serde(rename = "type")]
+    tx_type: String,
+    client: ClientId,
+    tx: TransactionId,
+    // Kept as text and parsed exactly, rather than letting the csv layer infer a float.
+    amount: Option<String>,
+    // Optional column. Without it every account is treated as single-currency.
+    currency: Option<String>,
+    // Optional free-text memo, kept for the transaction's history but otherwise ignored.
+    description: Option<String>,
+    // Legacy split amount columns, only read in split amount mode.
+    amount_whole: Option<String>,
+    amount_frac: Option<String>,
+    // `credit` or `debit`, only read for generic `movement` rows.
+    direction: Option<String>,
+    // Optional Unix time in seconds, only used to age disputes for auto-resolving.
+    timestamp: Option<u64>,
+}
+
+// Optional columns of a row that aren't part of the transaction itself.
+#[derive(Debug, Default, Clone)]
+struct RowMetadata {
+    currency: Option<String>,
+    description: Option<String>,
+    timestamp: Option<u64>,
+}
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("failed to read csv: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("failed to open input {path:?}")]
+    OpenFile {
+        path: PathBuf,
+        #[source]
+        file_error: io::Error,
+    },
+    #[error("failed to read input directory {path:?}")]
+    ReadDir {
+        path: PathBuf,
+        #[source]
+        dir_error: io::Error,
+    },
+    /// Applying one of the files from [`Engine::apply_transactions_from_dir`] failed partway.
+    #[error("failed to apply input {path:?}")]
+    File {
+        path: PathBuf,
+        #[source]
+        file_error: Box<Self>,
+    },
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// Flushing the accounts output failed. Unlike [`EngineError::Io`] this is always on the
+    /// output side, so writing can be retried without reprocessing the input.
+    #[error("failed to flush output: {0}")]
+    Flush(#[source] io::Error),
+    #[error("input transaction validation error: {0}")]
+    InputValidation(String),
+    #[error("invalid amount: {0}")]
+    InvalidAmount(String),
+    /// Deposits, as `(client, tx)`, still disputed at the end of an input, see
+    /// [`Engine::with_require_disputes_closed`].
+    #[error("disputes still open at end of input: {0:?}")]
+    OpenDisputes(Vec<(ClientId, TransactionId)>),
+    #[cfg(feature = "parquet")]
+    #[error("failed to write parquet: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
+
+/// Why a transaction applied through one of the `try_*` methods wasn't applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum TransactionError {
+    #[error("account is locked")]
+    Locked,
+    #[error("transaction id has already been processed")]
+    Duplicate,
+    #[error("insufficient available funds")]
+    InsufficientFunds,
+    #[error("amount must be positive")]
+    InvalidAmount,
+    #[error("available balance would leave the configured bounds")]
+    BalanceBounds,
+    #[error("balance would overflow")]
+    BalanceOverflow,
+    #[error("withdrawals are blocked while a dispute is open")]
+    OpenDispute,
+    #[error("currency does not match the account's currency")]
+    CurrencyMismatch,
+    #[error("client id is reserved")]
+    ReservedClient,
+    #[error("client limit reached")]
+    ClientLimit,
+    #[error("withdrawal velocity limit reached")]
+    VelocityLimit,
+    #[error("transaction does not reference a disputable deposit")]
+    NotApplicable,
+    #[error("deposit is in the wrong state for this transition")]
+    InvalidTransition,
+    #[error("client does not exist")]
+    UnknownClient,
+    #[error("client already has transactions")]
+    HasTransactions,
+}
+
+/// Why [`Engine::verify_roundtrip`] found the accounts output unfaithful.
+#[derive(Debug, Error)]
+pub enum RoundtripError {
+    #[error("failed to write accounts: {0}")]
+    Write(#[source] EngineError),
+    #[error("failed to read accounts back: {0}")]
+    Read(#[from] csv::Error),
+    #[error("client {client} {column} was written as {written}, expected {expected}")]
+    Mismatch {
+        client: ClientId,
+        column: &'static str,
+        expected: String,
+        written: String,
+    },
+    #[error("{written} account rows were written for {expected} accounts")]
+    RowCount { expected: usize, written: usize },
+}
+
+/// Why [`Engine::undo_last`] couldn't undo anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum UndoError {
+    #[error("undo is not enabled")]
+    Disabled,
+    #[error("no transaction left to undo")]
+    NothingToUndo,
+}
+
+// A dispute, resolve or chargeback recently applied, for coalescing repeats of it.
+#[derive(Debug, Clone, Copy)]
+struct RecentDisputeStep {
+    kind: &'static str,
+    client: ClientId,
+    tx: TransactionId,
+    // The deposit's state right after the step was applied. A repeat is only coalesced while the
+    // deposit is still in it, so anything that moved the deposit on in between, like an undo,
+    // lets the next step through.
+    state: TransactionState,
+    // `processed_count` when the step was last seen.
+    seen_at: u64,
+    // Whether coalescing it has been logged yet.
+    logged: bool,
+}
+
+// What `Engine::undo_last` needs to take back one applied transaction.
+#[derive(Debug, Clone)]
+struct UndoEntry {
+    client: ClientId,
+    tx: TransactionId,
+    // `None` if the transaction created the account.
+    checkpoint: Option<Checkpoint>,
+    // Whether the transaction claimed its id in the duplicate set.
+    claimed_id: bool,
+    input_checksum: u64,
+    // The summary before the transaction, replaced by the counts it added once it's applied.
+    summary: ProcessingSummary,
+    // The client's withdrawal velocity, the dispute's opening time and the latest timestamp
+    // before the transaction.
+    velocity: Option<(usize, Decimal)>,
+    dispute_opened_at: Option<u64>,
+    latest_timestamp: Option<u64>,
+}
+
+impl Engine {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an engine with room for `accounts` clients and `transactions` transaction ids, so
+    /// large batch jobs don't repeatedly rehash as the maps grow. With the `btree` feature there is
+    /// nothing to presize for accounts.
+    #[must_use]
+    pub fn with_capacity(accounts: usize, transactions: usize) -> Self {
+        Self {
+            #[cfg(not(feature = "btree"))]
+            accounts: AccountMap::with_capacity(accounts),
+            transaction_ids_processed: HashSet::with_capacity(transactions),
+            client_order: Vec::with_capacity(accounts),
+            ..Self::default()
+        }
+    }
+
+    /// Chooses where [`Engine::write_accounts`] takes `total` from, for auditors comparing the
+    /// running total with `available + held`. Defaults to [`TotalSource::Computed`]. Reconciled
+    /// totals only apply to computed ones.
+    #[must_use]
+    pub const fn with_total_source(mut self, source: TotalSource) -> Self {
+        self.config.total_source = source;
+        self
+    }
+
+    /// Writes `total` as the sum of the already-rounded `available` and `held` columns instead of
+    /// rounding the exact total independently. Off by default.
+    #[must_use]
+    pub const fn with_reconciled_totals(mut self, enabled: bool) -> Self {
+        self.config.reconcile_totals = enabled;
+        self
+    }
+
+    /// Reports dispute/resolve/chargeback rows against a deposit in the wrong state (e.g. resolving
+    /// one that isn't disputed, or anything after a chargeback) as
+    /// [`TransactionOutcome::InvalidTransition`], and rows naming a client that never existed as
+    /// [`TransactionOutcome::UnknownClient`]. Both are counted in the summary rather than silently
+    /// ignored, as are deposits reusing the id of a charged-back deposit. Off by default.
+    #[must_use]
+    pub const fn with_strict_state_transitions(mut self, enabled: bool) -> Self {
+        self.config.strict_state_transitions = enabled;
+        self
+    }
+
+    /// Skips (and counts) any transaction that would leave an account's `available` balance below
+    /// `min` or above `max`. Unlike an insufficient-funds check this also covers disputes and
+    /// resolves. Unbounded by default.
+    #[must_use]
+    pub fn with_balance_bounds(mut self, min: Option<Decimal>, max: Option<Decimal>) -> Self {
+        self.config.account_policy.min_available = min;
+        self.config.account_policy.max_available = max;
+        self.apply_account_policy();
+        self
+    }
+
+    /// Skips withdrawals, counted as insufficient funds, unless they leave at least `minimum` in
+    /// `available`. Zero by default, so an account can be emptied.
+    #[must_use]
+    pub fn with_minimum_balance(mut self, minimum: Decimal) -> Self {
+        self.config.account_policy.minimum_balance = minimum;
+        self.apply_account_policy();
+        self
+    }
+
+    /// Skips (and counts) withdrawals from any account with a disputed deposit, holding all funds
+    /// until the dispute is resolved or charged back. Off by default, where only `available` is
+    /// checked.
+    #[must_use]
+    pub fn with_block_withdrawals_during_dispute(mut self, enabled: bool) -> Self {
+        self.config.account_policy.block_withdrawals_during_dispute = enabled;
+        self.apply_account_policy();
+        self
+    }
+
+    /// Chooses how much a dispute holds when the disputed deposit has been partly withdrawn:
+    /// the full deposit (the default, which can take `available` negative) or only what's left in
+    /// `available`. Resolves and chargebacks release exactly what the dispute held.
+    #[must_use]
+    pub fn with_dispute_hold_basis(mut self, basis: DisputeHoldBasis) -> Self {
+        self.config.account_policy.dispute_hold_basis = basis;
+        self.apply_account_policy();
+        self
+    }
+
+    /// Writes every applied transaction to `writer` as a JSON object per line, as it happens. Write
+    /// failures don't stop processing; they're logged and counted in the summary.
+    #[must_use]
+    pub fn with_audit_writer<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.audit = AuditSink::new(writer);
+        self
+    }
+
+    /// Sets the order [`Engine::write_accounts`] emits accounts in.
+    #[must_use]
+    pub const fn with_output_order(mut self, order: OutputOrder) -> Self {
+        self.config.output_order = order;
+        self
+    }
+
+    /// Rejects input amounts with more than `scale` decimal places as
+    /// [`EngineError::InvalidAmount`] when they're parsed, instead of storing them and rounding on
+    /// output. Unlimited by default.
+    #[must_use]
+    pub const fn with_max_input_scale(mut self, scale: u32) -> Self {
+        self.config.max_input_scale = Some(scale);
+        self
+    }
+
+    /// Accepts amounts in scientific notation, such as `1.5e2` for `150`, by expanding them before
+    /// parsing. Exponents beyond what a `Decimal` can hold are still rejected. Off by default,
+    /// where only plain decimals are accepted.
+    #[must_use]
+    pub const fn with_lenient_amount_parsing(mut self, enabled: bool) -> Self {
+        self.config.lenient_amount_parsing = enabled;
+        self
+    }
+
+    /// Reads amounts like `1,50` as using a comma decimal separator, for exports from locales that
+    /// write them that way. Only takes effect together with
+    /// [`Engine::with_lenient_amount_parsing`]; otherwise amounts containing a comma are
+    /// rejected with an [`EngineError::InvalidAmount`] explaining the ambiguity.
+    #[must_use]
+    pub const fn with_decimal_comma(mut self, enabled: bool) -> Self {
+        self.config.decimal_comma = enabled;
+        self
+    }
+
+    /// Reads amounts from separate `amount_whole` and `amount_frac` columns, as written by some
+    /// legacy exports, instead of the `amount` column. The fraction holds the digits after the
+    /// decimal point, so `5` and `25` make `5.25`.
+    #[must_use]
+    pub const fn with_split_amount_columns(mut self, enabled: bool) -> Self {
+        self.config.split_amount_columns = enabled;
+        self
+    }
+
+    /// Chooses how rows missing an `amount` are handled. They're rejected by default.
+    #[must_use]
+    pub const fn with_missing_amount_policy(mut self, policy: MissingAmountPolicy) -> Self {
+        self.config.missing_amount_policy = policy;
+        self
+    }
+
+    /// Coalesces a dispute, resolve or chargeback that repeats one applied to the same transaction
+    /// within the last `window` transactions, as noisy feeds replay them. A repeat is only dropped
+    /// while the deposit is still in the state the applied step left it in, so coalescing never
+    /// changes balances: it just skips the repeat's warning. Dropped repeats are counted in
+    /// [`ProcessingSummary::coalesced_dispute_steps`], and only the first of a run is logged.
+    /// Strict state transitions count every repeat, so they disable coalescing. Zero, the
+    /// default, disables it too.
+    #[must_use]
+    pub const fn with_dispute_coalescing(mut self, window: usize) -> Self {
+        self.config.dispute_coalesce_window = window;
+        self
+    }
+
+    /// Resolves, when the engine is [finalized](Engine::finalize), every dispute that has been open
+    /// for longer than `after`, returning its held funds to `available`. Ages come from the
+    /// optional `timestamp` column (Unix seconds): a dispute is as old as the time between its row
+    /// and the latest timestamp seen. Disputes raised on rows without a timestamp are never
+    /// auto-resolved. Off by default.
+    #[must_use]
+    pub const fn with_dispute_auto_resolve(mut self, after: Duration) -> Self {
+        self.config.dispute_auto_resolve = Some(after);
+        self
+    }
+
+    /// Applies input `deposit` rows with a negative amount as withdrawals of the absolute amount,
+    /// for feeds that encode withdrawals that way. They go through the usual withdrawal checks,
+    /// insufficient funds included. Off by default, where negative deposits are skipped as
+    /// invalid amounts.
+    #[must_use]
+    pub const fn with_negative_deposit_as_withdrawal(mut self, enabled: bool) -> Self {
+        self.config.negative_deposit_as_withdrawal = enabled;
+        self
+    }
+
+    /// Chooses how balances, and zeros in particular, are printed by [`Engine::write_accounts`].
+    #[must_use]
+    pub const fn with_zero_display(mut self, display: ZeroDisplay) -> Self {
+        self.config.zero_display = display;
+        self
+    }
+
+    /// Detects duplicate transaction ids with a fixed-size bloom filter instead of an exact set, to
+    /// bound memory when the input uses a huge id space: the exact set can grow to hundreds of MB
+    /// over the full `u32` range. The tradeoff is that a small fraction of new ids look like
+    /// duplicates and are wrongly skipped; a duplicate is never let through. Size the filter with
+    /// [`Engine::with_bloom_sizing`]. Off by default.
+    #[must_use]
+    pub const fn with_bloom_dedup(mut self, enabled: bool) -> Self {
+        self.config.bloom_dedup = enabled;
+        self
+    }
+
+    /// Sizes the [`Engine::with_bloom_dedup`] filter for `expected_ids` ids at
+    /// `false_positive_rate`. Beyond `expected_ids` the rate climbs. Defaults to ten million ids
+    /// at 0.0001, about 24 MB.
+    #[must_use]
+    pub const fn with_bloom_sizing(mut self, expected_ids: usize, false_positive_rate: f64) -> Self {
+        self.config.bloom_sizing = Some((expected_ids, false_positive_rate));
+        self
+    }
+
+    /// Holds back disputes of a transaction that hasn't been seen yet and retries them once the
+    /// rest of the batch (one [`Engine::apply_transactions`] input or [`Engine::apply_batch`]
+    /// call) is applied, so a dispute reordered ahead of its deposit still lands. Disputes still
+    /// without a deposit after the retry are ignored as usual. Off by default.
+    #[must_use]
+    pub const fn with_dispute_buffering(mut self, enabled: bool) -> Self {
+        self.config.dispute_buffering = enabled;
+        self
+    }
+
+    /// Skips input lines starting with `comment`, such as `Some(b'#')`. By default there is no
+    /// comment character. Blank and whitespace-only lines are always skipped.
+    #[must_use]
+    pub const fn with_comment_char(mut self, comment: Option<u8>) -> Self {
+        self.config.comment_char = comment;
+        self
+    }
+
+    /// Sets which parts of the input have surrounding whitespace trimmed. Defaults to
+    /// [`Trim::All`]; [`Trim::None`] or [`Trim::Headers`] keep significant spaces in fields such as
+    /// a `description`, but then numeric fields must not be padded.
+    #[must_use]
+    pub const fn with_trim(mut self, trim: Trim) -> Self {
+        self.config.input_trim = Some(trim);
+        self
+    }
+
+    /// Sets how many decimal places [`Engine::write_accounts`] rounds each balance column to, for
+    /// downstream systems that want e.g. `total` at two places. Defaults to four for every column.
+    #[must_use]
+    pub const fn with_column_precision(mut self, precision: ColumnPrecision) -> Self {
+        self.config.column_precision = precision;
+        self
+    }
+
+    /// Clamps a negative `available` or `held` within `epsilon` of zero back to exactly zero after
+    /// each applied transaction, so rounding residue doesn't leave balances logically negative. Off
+    /// by default.
+    #[must_use]
+    pub const fn with_epsilon_clamp(mut self, epsilon: Decimal) -> Self {
+        self.config.epsilon_clamp = Some(epsilon);
+        self
+    }
+
+    /// Sets when [`Engine::suspicious_patterns`] flags a client.
+    #[must_use]
+    pub const fn with_suspicious_pattern_thresholds(mut self, thresholds: SuspiciousPatternThresholds) -> Self {
+        self.config.suspicious_thresholds = thresholds;
+        self
+    }
+
+    /// Sets how [`Engine::write_accounts`] quotes fields, for downstream parsers that are strict
+    /// about quoting. Defaults to quoting only where necessary.
+    #[must_use]
+    pub const fn with_output_quoting(mut self, quoting: QuoteStyle) -> Self {
+        self.config.output_quoting = quoting;
+        self
+    }
+
+    /// Requires every dispute to be resolved or charged back by the end of each input read by
+    /// [`Engine::apply_transactions`]. Deposits still disputed then fail the read with
+    /// [`EngineError::OpenDisputes`] under strict state transitions, and are otherwise counted in
+    /// [`ProcessingSummary::open_disputes_at_end`]. Off by default.
+    #[must_use]
+    pub const fn with_require_disputes_closed(mut self, enabled: bool) -> Self {
+        self.config.require_disputes_closed = enabled;
+        self
+    }
+
+    /// Counts resolves and chargebacks of a deposit that hasn't been disputed yet in
+    /// [`ProcessingSummary::out_of_order_lifecycle`], which usually means the feed was reordered.
+    /// The transactions are handled as usual either way.
+    #[must_use]
+    pub const fn with_lifecycle_order_tracking(mut self, enabled: bool) -> Self {
+        self.config.track_lifecycle_order = enabled;
+        self
+    }
+
+    /// Chooses how seeded account rows with an inconsistent `total` are handled. They're rejected
+    /// by default.
+    #[must_use]
+    pub const fn with_total_mismatch_policy(mut self, policy: TotalMismatchPolicy) -> Self {
+        self.config.total_mismatch_policy = policy;
+        self
+    }
+
+    /// Accepts seeded account rows with nonzero `held`. They're rejected by default, since the
+    /// disputes holding the funds aren't seeded and nothing can release them afterwards.
+    #[must_use]
+    pub const fn with_seeded_held(mut self, enabled: bool) -> Self {
+        self.config.seeded_held = enabled;
+        self
+    }
+
+    /// Limits each client to `count` withdrawals totalling at most `amount` per batch, that is per
+    /// call to [`Engine::apply_transactions`] or one of its variants, or to
+    /// [`Engine::apply_batch`]. Withdrawals past either limit are skipped and counted.
+    /// Unlimited by default.
+    #[must_use]
+    pub const fn with_withdrawal_velocity_limit(mut self, count: usize, amount: Decimal) -> Self {
+        self.config.withdrawal_velocity_limit = Some((count, amount));
+        self
+    }
+
+    /// Also locks an account the first time it hits the withdrawal velocity limit, with
+    /// [`LockReason::VelocityLimit`]. Off by default.
+    #[must_use]
+    pub const fn with_freeze_on_velocity_limit(mut self, enabled: bool) -> Self {
+        self.config.freeze_on_velocity_limit = enabled;
+        self
+    }
+
+    /// Caps the number of distinct clients at `max`, bounding memory against input that invents
+    /// client ids. Once reached, transactions that would open a new account are skipped and
+    /// counted, while existing clients carry on as usual. Unbounded by default.
+    #[must_use]
+    pub const fn with_max_clients(mut self, max: usize) -> Self {
+        self.config.max_clients = Some(max);
+        self
+    }
+
+    /// Skips (and counts) every transaction for a client id in `clients`, e.g. `0..=0` when client
+    /// 0 is a system account. Nothing is reserved by default.
+    #[must_use]
+    pub const fn with_reserved_clients(mut self, clients: RangeInclusive<ClientId>) -> Self {
+        self.config.reserved_clients = Some(clients);
+        self
+    }
+
+    /// Flushes the output every `rows` account rows in [`Engine::write_accounts`], so slow sinks
+    /// see progress and less is buffered. By default, or with `rows` of zero, output is flushed
+    /// once at the end.
+    #[must_use]
+    pub const fn with_flush_every(mut self, rows: usize) -> Self {
+        self.config.flush_every = NonZeroUsize::new(rows);
+        self
+    }
+
+    /// Rejects inputs whose header row has an unexpected, duplicate or missing column with
+    /// [`EngineError::InputValidation`], before any row is applied. By default such headers are
+    /// only logged, and unknown columns are ignored.
+    #[must_use]
+    pub const fn with_strict_headers(mut self, enabled: bool) -> Self {
+        self.config.strict_headers = enabled;
+        self
+    }
+
+    /// Keeps enough to undo the last `depth` applied transactions with [`Engine::undo_last`]. Older
+    /// ones are forgotten, bounding the memory used. Zero, the default, disables undo.
+    #[must_use]
+    pub fn with_undo(mut self, depth: usize) -> Self {
+        self.config.undo_depth = depth;
+        self.undo_log = VecDeque::with_capacity(depth);
+        self
+    }
+
+    /// Measures wall-clock time spent parsing rows and applying them in
+    /// [`Engine::apply_transactions`], reported as [`ProcessingSummary::parse_nanos`] and
+    /// [`ProcessingSummary::apply_nanos`]. Off by default to avoid reading the clock per row.
+    #[must_use]
+    pub const fn with_timing(mut self, enabled: bool) -> Self {
+        self.config.timing = enabled;
+        self
+    }
+
+    /// Wraps the processing of every transaction in an `info` level `transaction` span carrying its
+    /// `client`, `tx` and `kind`, so logs can be filtered per transaction in a collector. Off by
+    /// default to avoid the per-row overhead.
+    #[must_use]
+    pub const fn with_per_transaction_spans(mut self, enabled: bool) -> Self {
+        self.config.per_transaction_spans = enabled;
+        self
+    }
+
+    /// Counts of how every transaction seen so far was handled.
+    #[must_use]
+    pub const fn summary(&self) -> &ProcessingSummary {
+        &self.summary
+    }
+
+    /// Reverts the most recently applied transaction that hasn't been undone yet, restoring the
+    /// affected account and releasing the transaction id for reuse (except with
+    /// [`Engine::with_bloom_dedup`], which can't forget ids). The summary counts, the client's
+    /// withdrawal velocity and the dispute's age for auto-resolving go back to how they were
+    /// before it. Skipped transactions aren't undone, and neither is anything already written to
+    /// the audit sink. Neither is the [`Engine::take_deltas`] baseline: a transaction whose delta
+    /// was already taken shows up reversed in the next one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UndoError::Disabled`] without [`Engine::with_undo`], and
+    /// [`UndoError::NothingToUndo`] once the log is empty.
+    pub fn undo_last(&mut self) -> Result<(), UndoError> {
+        if self.config.undo_depth == 0 {
+            return Err(UndoError::Disabled);
+        }
+        let entry = self.undo_log.pop_back().ok_or(UndoError::NothingToUndo)?;
+        if let Some(checkpoint) = entry.checkpoint {
+            if let Some(account) = self.accounts.get_mut(&entry.client) {
+                account.restore(checkpoint);
+            }
+        } else {
+            self.accounts.remove(&entry.client);
+            self.client_order.retain(|client| *client != entry.client);
+        }
+        if entry.claimed_id && !self.config.bloom_dedup {
+            self.transaction_ids_processed.remove(&entry.tx);
+        }
+        self.input_checksum = entry.input_checksum;
+        self.summary.retract(&entry.summary);
+        match entry.velocity {
+            Some(velocity) => self.withdrawal_velocity.insert(entry.client, velocity),
+            None => self.withdrawal_velocity.remove(&entry.client),
+        };
+        match entry.dispute_opened_at {
+            Some(opened_at) => self.dispute_opened_at.insert((entry.client, entry.tx), opened_at),
+            None => self.dispute_opened_at.remove(&(entry.client, entry.tx)),
+        };
+        self.latest_timestamp = entry.latest_timestamp;
+        Ok(())
+    }
+
+    /// Every deposit, withdrawal and adjustment id claimed so far, in no particular order. This
+    /// includes ids of rows that were rejected once claimed, such as withdrawals with insufficient
+    /// funds, so it can be diffed against what was sent upstream. Empty after [`Engine::finalize`],
+    /// and with [`Engine::with_bloom_dedup`], which doesn't keep the ids themselves.
+    pub fn processed_transaction_ids(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.transaction_ids_processed.iter().copied()
+    }
+
+    /// Checksum of every transaction applied so far, chained in order. Engines that applied the
+    /// same transactions in the same order agree on it, which makes it cheap to compare replicas.
+    /// Skipped rows don't contribute, and amounts are compared by value, so `1.0` and `1.00` match.
+    #[must_use]
+    pub const fn input_checksum(&self) -> u64 {
+        self.input_checksum
+    }
+
+    /// Ends the engine's lifecycle, returning a read-only [`Finalized`] view. The duplicate
+    /// transaction id set is only needed while applying transactions, so it's dropped here. Stale
+    /// disputes are auto-resolved first, see [`Engine::with_dispute_auto_resolve`].
+    #[must_use]
+    pub fn finalize(mut self) -> Finalized {
+        self.auto_resolve_stale_disputes();
+        self.transaction_ids_processed = HashSet::new();
+        self.bloom_ids = None;
+        Finalized { engine: self }
+    }
+
+    /// Streams transactions from a CSV reader, applying each valid row in order. Malformed rows are
+    /// logged and skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::Io`] if the underlying reader fails. Transactions before the failure
+    /// stay applied. With [`Engine::with_strict_headers`], returns
+    /// [`EngineError::InputValidation`] for a bad header row.
+    pub fn apply_transactions<R: Read>(&mut self, reader: R) -> Result<(), EngineError> {
+        self.apply_rows(reader, None)
+    }
+
+    /// Like [`Engine::apply_transactions`], but also returns every row that wasn't applied, both
+    /// malformed rows and transactions that were skipped, for triage.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Engine::apply_transactions`].
+    pub fn apply_transactions_collecting<R: Read>(&mut self, reader: R) -> Result<Vec<RowError>, EngineError> {
+        let mut errors = Vec::new();
+        self.apply_rows(reader, Some(&mut errors))?;
+        Ok(errors)
+    }
+
+    // Reports rows that weren't applied to `errors`, if given. Rows are only rendered back to text
+    // when they're collected.
+    fn apply_rows<R: Read>(&mut self, reader: R, mut errors: Option<&mut Vec<RowError>>) -> Result<(), EngineError> {
+        self.withdrawal_velocity.clear();
+        let mut csv_reader = self.input_reader(reader);
+        let headers = csv_reader.headers()?.clone();
+        self.check_input_headers(&headers)?;
+        // Time is attributed at each lap, so rows skipped while parsing count towards the next
+        // parse lap.
+        let mut clock = self.config.timing.then(Instant::now);
+        let mut orphan_disputes = Vec::new();
+        for (line, record) in csv_reader.records().enumerate() {
+            let record = match record.map_err(reader_error) {
+                Ok(record) => record,
+                // The reader itself failed, so the rest of the stream can't be trusted. Only
+                // row-level errors are skippable.
+                Err(EngineError::Io(io_error)) => {
+                    self.retry_orphan_disputes(orphan_disputes, errors);
+                    return Err(EngineError::Io(io_error));
+                }
+                Err(err) => {
+                    warn!(line, error = %err, "Skipping malformed transaction row");
+                    self.summary.malformed_rows += 1;
+                    if let Some(errors) = errors.as_deref_mut() {
+                        errors.push(RowError::malformed(&err, None));
+                    }
+                    continue;
+                }
+            };
+            let (input, metadata) = match self.parse_record(&record, &headers) {
+                Ok(Some(row)) => row,
+                // Whitespace-only lines carry no transaction.
+                Ok(None) => continue,
+                Err(err) => {
+                    warn!(line, error = %err, "Skipping malformed transaction row");
+                    self.summary.malformed_rows += 1;
+                    if let Some(errors) = errors.as_deref_mut() {
+                        errors.push(RowError::malformed(&err, Some(&record)));
+                    }
+                    continue;
+                }
+            };
+            self.summary.parse_nanos = self.summary.parse_nanos.saturating_add(lap(&mut clock));
+
+            if self.is_orphan_dispute(&input) {
+                orphan_disputes.push((input, metadata, errors.is_some().then_some(record)));
+                continue;
+            }
+            let outcome = self.process_record_with_metadata(input, &metadata);
+            if let (Some(errors), Err(err)) = (errors.as_deref_mut(), outcome_result(outcome)) {
+                errors.push(RowError::skipped(err, input, &metadata, &record));
+            }
+            self.summary.apply_nanos = self.summary.apply_nanos.saturating_add(lap(&mut clock));
+        }
+        self.summary.parse_nanos = self.summary.parse_nanos.saturating_add(lap(&mut clock));
+        self.retry_orphan_disputes(orphan_disputes, errors);
+        self.summary.apply_nanos = self.summary.apply_nanos.saturating_add(lap(&mut clock));
+
+        self.check_disputes_closed()
+    }
+
+    /// Like [`Engine::apply_transactions`], but processes one row per call to `next`, so the
+    /// caller decides when to continue and can yield to other work in between. Each item is the
+    /// outcome of one transaction, or the error for a row that was skipped, which is still counted
+    /// as malformed. Dispute buffering and timing only apply to the batch APIs.
+    ///
+    /// The iterator ends after an I/O error, a bad header row under
+    /// [`Engine::with_strict_headers`], or the end of the input.
+    pub fn process_iter<'a, R: Read + 'a>(
+        &'a mut self,
+        reader: R,
+    ) -> impl Iterator<Item = Result<TransactionOutcome, EngineError>> + 'a {
+        self.withdrawal_velocity.clear();
+        let mut csv_reader = self.input_reader(reader);
+        let (mut header_error, headers) = match csv_reader.headers() {
+            Ok(headers) => (self.check_input_headers(headers).err(), headers.clone()),
+            Err(err) => (Some(EngineError::Csv(err)), csv::StringRecord::new()),
+        };
+        let mut records = csv_reader.into_records();
+        let mut finished = false;
+        std::iter::from_fn(move || {
+            if finished {
+                return None;
+            }
+            if let Some(error) = header_error.take() {
+                finished = true;
+                return Some(Err(error));
+            }
+            loop {
+                let row = records
+                    .next()?
+                    .map_err(reader_error)
+                    .and_then(|record| self.parse_record(&record, &headers));
+                return match row {
+                    Ok(Some((input, metadata))) => Some(Ok(self.process_record_with_metadata(input, &metadata))),
+                    Ok(None) => continue,
+                    Err(EngineError::Io(io_error)) => {
+                        finished = true;
+                        Some(Err(EngineError::Io(io_error)))
+                    }
+                    Err(err) => {
+                        self.summary.malformed_rows += 1;
+                        Some(Err(err))
+                    }
+                };
+            }
+        })
+    }
+
+    /// Applies transactions from fixed-width lines, such as mainframe exports, with fields at the
+    /// byte offsets in `layout`. There is no header line. Blank lines are skipped, and lines that
+    /// don't parse are counted as malformed, like CSV rows. Amounts are parsed with the same
+    /// options as [`Engine::apply_transactions`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::Io`] if the input can't be read or isn't UTF-8. Lines before the
+    /// failing one stay applied.
+    pub fn apply_transactions_fixed_width<R: Read>(
+        &mut self,
+        reader: R,
+        layout: &FixedWidthLayout,
+    ) -> Result<(), EngineError> {
+        self.withdrawal_velocity.clear();
+        for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.parse_fixed_width(&line, layout) {
+                Ok(input) => {
+                    self.process_record(input);
+                }
+                Err(err) => {
+                    warn!(line = line_number, error = %err, "Skipping malformed transaction row");
+                    self.summary.malformed_rows += 1;
+                }
+            }
+        }
+        self.check_disputes_closed()
+    }
+
+    fn parse_fixed_width(&self, line: &str, layout: &FixedWidthLayout) -> Result<InputTransaction, EngineError> {
+        let field = |range: &Range<usize>| {
+            let end = range.end.min(line.len());
+            line.get(range.start.min(end)..end)
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+        };
+        let invalid = |name| EngineError::InputValidation(format!("invalid {name} in fixed-width line {line:?}"));
+        self.parse_transaction(RawInputTransaction {
+            tx_type: field(&layout.tx_type).unwrap_or_default().to_owned(),
+            client: field(&layout.client)
+                .and_then(|client| client.parse().ok())
+                .ok_or_else(|| invalid("client"))?,
+            tx: field(&layout.tx)
+                .and_then(|tx| tx.parse().ok())
+                .ok_or_else(|| invalid("tx"))?,
+            amount: field(&layout.amount).map(str::to_owned),
+            currency: None,
+            description: None,
+            amount_whole: None,
+            amount_frac: None,
+            direction: None,
+            timestamp: None,
+        })
+    }
+
+    fn input_reader<R: Read>(&self, reader: R) -> csv::Reader<R> {
+        csv::ReaderBuilder::new()
+            .trim(self.config.input_trim.unwrap_or(Trim::All))
+            .flexible(true)
+            .has_headers(true)
+            .comment(self.config.comment_char)
+            .from_reader(reader)
+    }
+
+    // Bad headers are only logged unless strict headers are enabled.
+    fn check_input_headers(&self, headers: &csv::StringRecord) -> Result<(), EngineError> {
+        let amount_columns: &[&str] = if self.config.split_amount_columns {
+            &SPLIT_AMOUNT_COLUMNS
+        } else {
+            &AMOUNT_COLUMNS
+        };
+        if let Err(problem) = check_headers(headers, amount_columns) {
+            if self.config.strict_headers {
+                return Err(EngineError::InputValidation(problem));
+            }
+            warn!(problem, "Unexpected input header row");
+        }
+        Ok(())
+    }
+
+    // Parses one CSV record into a transaction and its metadata, or `None` for a whitespace-only
+    // line.
+    fn parse_record(
+        &self,
+        record: &csv::StringRecord,
+        headers: &csv::StringRecord,
+    ) -> Result<Option<(InputTransaction, RowMetadata)>, EngineError> {
+        if record.iter().all(|field| field.trim().is_empty()) {
+            return Ok(None);
+        }
+        let mut raw_input = record
+            .deserialize::<RawInputTransaction>(Some(headers))
+            .map_err(|error| id_range_error(record, headers).unwrap_or(EngineError::Csv(error)))?;
+        let metadata = RowMetadata {
+            currency: raw_input.currency.take(),
+            description: raw_input.description.take(),
+            timestamp: raw_input.timestamp,
+        };
+        Ok(Some((self.parse_transaction(raw_input)?, metadata)))
+    }
+
+    /// Processes CSV transactions held in a string and returns the resulting accounts as CSV,
+    /// sorted by client id.
+    ///
+    /// ```
+    /// let output = payments_engine::Engine::process_str(
+    ///     "type,client,tx,amount\ndeposit,2,1,2.0\ndeposit,1,2,1.5\nwithdrawal,1,3,0.5\n",
+    /// )?;
+    /// assert_eq!(
+    ///     output,
+    ///     "client,available,held,total,locked\n1,1,0,1,false\n2,2,0,2,false\n"
+    /// );
+    /// # Ok::<(), payments_engine::EngineError>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input can't be read, see [`Engine::apply_transactions`].
+    pub fn process_str(input: &str) -> Result<String, EngineError> {
+        let mut engine = Self::new().with_output_order(OutputOrder::Sorted);
+        engine.apply_transactions(input.as_bytes())?;
+        let mut output = Vec::new();
+        engine.finalize().write_accounts(&mut output)?;
+        String::from_utf8(output).map_err(|error| EngineError::InputValidation(error.to_string()))
+    }
+
+    /// Seeds account balances from a previous [`Engine::write_accounts`] output, so the next batch
+    /// can continue from it. Seeded accounts replace any existing ones for the same client.
+    ///
+    /// Only balances and lock state are restored, not transaction history: disputes of
+    /// transactions from earlier batches are ignored, so rows with `held` funds are rejected unless
+    /// [`Engine::with_seeded_held`] accepts them, and then they stay held. Seeded clients are
+    /// admitted like clients of input transactions, so reserved clients and new clients past the
+    /// client limit are rejected.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::Csv`] for an unreadable row, or [`EngineError::InputValidation`] for
+    /// a reserved client, a new client past the limit, unaccepted `held` funds, or a row whose
+    /// `available` and `held` don't add up to its `total` when the [`TotalMismatchPolicy`]
+    /// rejects it. Rows before the failing one stay seeded.
+    pub fn seed_from_accounts_csv<R: Read>(&mut self, reader: R) -> Result<(), EngineError> {
+        #[derive(Deserialize)]
+        struct AccountRow {
+            client: ClientId,
+            available: Decimal,
+            held: Decimal,
+            total: Decimal,
+            locked: bool,
+        }
+
+        let mut csv_reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        for row in csv_reader.deserialize::<AccountRow>() {
+            let row = row?;
+            if self
+                .config
+                .reserved_clients
+                .as_ref()
+                .is_some_and(|reserved| reserved.contains(&row.client))
+            {
+                return Err(EngineError::InputValidation(format!(
+                    "client {} is reserved",
+                    row.client
+                )));
+            }
+            if !self.accounts.contains_key(&row.client)
+                && self.config.max_clients.is_some_and(|max| self.accounts.len() >= max)
+            {
+                return Err(EngineError::InputValidation(format!(
+                    "client {} is past the client limit",
+                    row.client
+                )));
+            }
+            if !row.held.is_zero() && !self.config.seeded_held {
+                return Err(EngineError::InputValidation(format!(
+                    "client {} has {} held with no dispute to release it",
+                    row.client, row.held
+                )));
+            }
+            let sum = row.available.checked_add(row.held);
+            let available = match (sum == Some(row.total), self.config.total_mismatch_policy) {
+                (true, _) => Some(row.available),
+                (false, TotalMismatchPolicy::Reject) => None,
+                (false, TotalMismatchPolicy::Recompute) => {
+                    warn!(client = row.client, total = %row.total, "Recomputing inconsistent seeded total");
+                    sum.map(|_| row.available)
+                }
+                (false, TotalMismatchPolicy::TrustTotal) => {
+                    warn!(client = row.client, available = %row.available, "Deriving available from seeded total");
+                    row.total.checked_sub(row.held)
+                }
+            };
+            let Some(available) = available else {
+                return Err(EngineError::InputValidation(format!(
+                    "client {} available {} and held {} don't add up to total {}",
+                    row.client, row.available, row.held, row.total
+                )));
+            };
+
+            let account = Account {
+                available,
+                held: row.held,
+                stored_total: available + row.held,
+                locked: row.locked,
+                ..Account::with_policy(self.config.account_policy)
+            };
+            if self.accounts.insert(row.client, account).is_none() {
+                self.client_order.push(row.client);
+            }
+        }
+        Ok(())
+    }
+
+    /// Builder form of [`Engine::seed_from_accounts_csv`], for starting a service from a
+    /// configuration and previous output in one expression. The accounts output carries no
+    /// precision or policy of its own, so everything configured on the engine applies to the
+    /// seeded accounts, whether it's set before or after seeding. The exceptions are the client
+    /// admission and seeded `held` checks, which must be configured before seeding.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Engine::seed_from_accounts_csv`].
+    pub fn with_seeded_accounts<R: Read>(mut self, reader: R) -> Result<Self, EngineError> {
+        self.seed_from_accounts_csv(reader)?;
+        Ok(self)
+    }
+
+    /// Opens `path` and applies its transactions, see [`Engine::apply_transactions`]. With the
+    /// `bzip2` or `zstd` features, `.bz2` and `.zst` files are decompressed as they're read.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::OpenFile`] if the file cannot be opened, or any error from reading
+    /// it.
+    pub fn apply_transactions_from_file(&mut self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+        self.apply_rows(open_input(path.as_ref())?, None)
+    }
+
+    /// Opens `path` and applies its transactions, returning the rows that weren't applied, see
+    /// [`Engine::apply_transactions_collecting`].
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Engine::apply_transactions_from_file`].
+    pub fn apply_transactions_from_file_collecting(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<RowError>, EngineError> {
+        self.apply_transactions_collecting(open_input(path.as_ref())?)
+    }
+
+    /// Re-attempts rows that an earlier [`Engine::apply_transactions_collecting`] skipped, in
+    /// order, for two-pass ingestion where e.g. a dispute arrived before its deposit. Returns the
+    /// counts for just this pass. Malformed rows can't be retried and are counted as malformed
+    /// again. The engine's own [`Engine::summary`] counts the retries as well.
+    pub fn retry_skipped(&mut self, skipped: &[RowError]) -> ProcessingSummary {
+        let mut summary = ProcessingSummary::default();
+        for row_error in skipped {
+            if let Some(transaction) = row_error.transaction {
+                summary.record(self.process_record_with_metadata(transaction, &row_error.metadata));
+            } else {
+                summary.malformed_rows += 1;
+            }
+        }
+        summary
+    }
+
+    /// Applies the CSV transactions in the file at `path` like [`Engine::apply_transactions`], but
+    /// parses them straight from a memory map of the file instead of through read calls, for very
+    /// large inputs. Compressed files aren't decompressed.
+    ///
+    /// The file must not be modified or truncated by anyone while it's being processed. Changed
+    /// contents show up as garbled rows, and on most platforms a truncated mapping kills the
+    /// process with a bus error.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::OpenFile`] if the file can't be opened or mapped, otherwise the same
+    /// as [`Engine::apply_transactions`].
+    #[cfg(feature = "mmap")]
+    pub fn apply_transactions_from_mmap(&mut self, path: &Path) -> Result<(), EngineError> {
+        let open_error = |error| EngineError::OpenFile {
+            path: path.to_path_buf(),
+            file_error: error,
+        };
+        let file = File::open(path).map_err(open_error)?;
+        // SAFETY: the map is only read through while this call runs, and the documented contract
+        // is that nobody mutates or truncates the file meanwhile. That's all `Mmap::map` needs.
+        #[allow(unsafe_code)]
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(open_error)?;
+        self.apply_transactions(&map[..])
+    }
+
+    /// Applies CSV transactions like [`Engine::apply_transactions`], but on `shards` worker
+    /// threads, each owning the clients whose id modulo `shards` is its index. The calling thread
+    /// reads and parses rows and hands them to the workers over channels holding at most
+    /// `channel_depth` rows each, so a reader that outpaces the workers blocks instead of
+    /// buffering the input. Each client's transactions still apply in input order.
+    ///
+    /// Duplicate transaction ids and the client limit are checked on the reading thread across
+    /// all shards, so they're enforced as in [`Engine::apply_transactions`], with one difference:
+    /// an id is claimed as soon as its row is handed to a worker, even if the worker then skips it,
+    /// say for a locked account, and a repeat of it is always counted as a duplicate.
+    /// Sharded transactions aren't audited, chained into [`Engine::input_checksum`] or undoable,
+    /// and the undo log is cleared. Disputes aren't buffered, timing isn't measured, and
+    /// [`OutputOrder::FirstSeen`] lists new clients shard by shard.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Engine::apply_transactions`]. Rows read before a reader failure stay applied.
+    pub fn apply_transactions_sharded<R: Read>(
+        &mut self,
+        reader: R,
+        shards: NonZeroUsize,
+        channel_depth: usize,
+    ) -> Result<(), EngineError> {
+        let mut csv_reader = self.input_reader(reader);
+        let headers = csv_reader.headers()?.clone();
+        self.check_input_headers(&headers)?;
+        let shard_of = |client: ClientId| usize::from(client) % shards.get();
+        let mut workers: Vec<_> = (0..shards.get())
+            .map(|_| Self {
+                ids_claimed_upstream: true,
+                config: self.config.clone(),
+                ..Self::default()
+            })
+            .collect();
+        // The reading thread's own engine, owning the id set so claims hold across shards.
+        let mut admission = Self {
+            transaction_ids_processed: mem::take(&mut self.transaction_ids_processed),
+            bloom_ids: self.bloom_ids.take(),
+            config: self.config.clone(),
+            ..Self::default()
+        };
+        let mut known_clients: HashSet<ClientId> = self.accounts.keys().copied().collect();
+        for (client, account) in mem::take(&mut self.accounts) {
+            workers[shard_of(client)].accounts.insert(client, account);
+        }
+
+        let reader_engine = &*self;
+        let (workers, malformed_rows, result) = thread::scope(|scope| {
+            let (senders, handles): (Vec<_>, Vec<_>) = workers
+                .into_iter()
+                .map(|mut worker| {
+                    let (sender, receiver) = mpsc::sync_channel::<(InputTransaction, RowMetadata)>(channel_depth);
+                    let handle = scope.spawn(move || {
+                        for (transaction, metadata) in receiver {
+                            worker.process_record_with_metadata(transaction, &metadata);
+                        }
+                        worker
+                    });
+                    (sender, handle)
+                })
+                .collect();
+            let mut malformed_rows = 0;
+            let mut result = Ok(());
+            for (line, record) in csv_reader.records().enumerate() {
+                let row = record
+                    .map_err(reader_error)
+                    .and_then(|record| reader_engine.parse_record(&record, &headers));
+                match row {
+                    Ok(Some((transaction, metadata))) => {
+                        if admission.admit_sharded_row(transaction, &mut known_clients).is_some() {
+                            continue;
+                        }
+                        let shard = shard_of(transaction.ids().client);
+                        // Only fails if the worker panicked, which joining it below re-raises.
+                        if senders[shard].send((transaction, metadata)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(EngineError::Io(io_error)) => {
+                        result = Err(EngineError::Io(io_error));
+                        break;
+                    }
+                    Err(err) => {
+                        warn!(line, error = %err, "Skipping malformed transaction row");
+                        malformed_rows += 1;
+                    }
+                }
+            }
+            drop(senders);
+            let workers: Vec<Self> = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect();
+            (workers, malformed_rows, result)
+        });
+
+        self.summary.malformed_rows += malformed_rows;
+        self.summary.absorb(&admission.summary);
+        self.transaction_ids_processed = admission.transaction_ids_processed;
+        self.bloom_ids = admission.bloom_ids;
+        self.undo_log.clear();
+        for worker in workers {
+            self.accounts.extend(worker.accounts);
+            self.client_order.extend(worker.client_order);
+            self.dispute_opened_at.extend(worker.dispute_opened_at);
+            self.latest_timestamp = self.latest_timestamp.max(worker.latest_timestamp);
+            self.summary.absorb(&worker.summary);
+        }
+        result?;
+        self.check_disputes_closed()
+    }
+
+    fn check_disputes_closed(&mut self) -> Result<(), EngineError> {
+        if !self.config.require_disputes_closed {
+            return Ok(());
+        }
+        let open: Vec<_> = self
+            .open_disputes()
+            .into_iter()
+            .map(|(client, tx, _)| (client, tx))
+            .collect();
+        if open.is_empty() {
+            return Ok(());
+        }
+        if self.config.strict_state_transitions {
+            return Err(EngineError::OpenDisputes(open));
+        }
+        warn!(count = open.len(), "Disputes still open at end of input");
+        self.summary.open_disputes_at_end += open.len();
+        Ok(())
+    }
+
+    /// Applies every file in `dir` whose name matches `glob` (`*` matches any run of characters,
+    /// `?` a single one), in lexical order of file name, so date-named batch files are
+    /// processed in order against shared state.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::ReadDir`] if `dir` cannot be listed, [`EngineError::OpenFile`]
+    /// naming the first file that cannot be opened, or [`EngineError::File`] naming the first file
+    /// that fails while it's applied, wrapping the error. Files before it stay applied.
+    pub fn apply_transactions_from_dir(&mut self, dir: impl AsRef<Path>, glob: &str) -> Result<(), EngineError> {
+        let dir = dir.as_ref();
+        let read_dir_error = |error| EngineError::ReadDir {
+            path: dir.to_path_buf(),
+            dir_error: error,
+        };
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir).map_err(read_dir_error)? {
+            let entry = entry.map_err(read_dir_error)?;
+            let matches = entry.file_name().to_str().is_some_and(|name| glob_matches(glob, name));
+            if matches && entry.file_type().map_err(read_dir_error)?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort_unstable();
+
+        for path in paths {
+            match self.apply_transactions_from_file(&path) {
+                Ok(()) => {}
+                Err(error @ EngineError::OpenFile { .. }) => return Err(error),
+                Err(error) => {
+                    return Err(EngineError::File {
+                        path,
+                        file_error: Box::new(error),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Listens on `addr`, accepts a single connection and applies the CSV transactions it sends
+    /// until the peer closes the stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if binding or accepting fails, or any error from reading the stream.
+    #[cfg(feature = "net")]
+    pub fn apply_transactions_from_socket(&mut self, addr: &str) -> Result<(), EngineError> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        let (stream, peer) = listener.accept()?;
+        tracing::info!(%peer, "Accepted transaction stream connection");
+        self.apply_transactions(stream)
+    }
+
+    /// Writes every account's balances to `writer` as CSV.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a row cannot be serialized or the writer fails, and
+    /// [`EngineError::Flush`] if flushing the writer fails.
+    pub fn write_accounts<W: Write>(&self, writer: W) -> Result<(), EngineError> {
+        self.write_accounts_filtered(writer, |_, _| true)
+    }
+
+    /// Writes the accounts `predicate` accepts to `writer`, in the same format and order as
+    /// [`Engine::write_accounts`]. For example, `|_, account| account.locked` writes only locked
+    /// accounts.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Engine::write_accounts`].
+    pub fn write_accounts_filtered<W: Write>(
+        &self,
+        writer: W,
+        predicate: impl Fn(ClientId, &AccountSnapshot) -> bool,
+    ) -> Result<(), EngineError> {
+        #[derive(serde::Serialize)]
+        struct AccountRow<'a> {
+            client: ClientId,
+            available: String,
+            held: String,
+            total: String,
+            locked: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            currency: Option<&'a str>,
+        }
+
+        // The currency column is only written if some account has one, and then for every row.
+        let with_currency = self.accounts.values().any(|account| account.currency.is_some());
+        let precision = self.config.column_precision;
+        let mut csv_writer = csv::WriterBuilder::new()
+            .quote_style(self.config.output_quoting)
+            .from_writer(writer);
+        let accounts = self
+            .ordered_accounts()
+            .into_iter()
+            .filter(|(client, account)| predicate(**client, &AccountSnapshot::from(*account)));
+        for (index, (client, account)) in accounts.enumerate() {
+            let total = self.output_total(account);
+            let row = AccountRow {
+                client: *client,
+                available: format_decimal(account.available, precision.available, self.config.zero_display),
+                held: format_decimal(account.held, precision.held, self.config.zero_display),
+                total: format_decimal(total, precision.total, self.config.zero_display),
+                locked: account.locked,
+                currency: with_currency.then(|| account.currency().unwrap_or_default()),
+            };
+            csv_writer.serialize(row)?;
+            if self.config.flush_every.is_some_and(|rows| (index + 1) % rows == 0) {
+                csv_writer.flush().map_err(EngineError::Flush)?;
+            }
+        }
+        csv_writer.flush().map_err(EngineError::Flush)?;
+        Ok(())
+    }
+
+    /// Writes every stored transaction to `writer` as CSV, one row per transaction with its client,
+    /// id, type, amount and dispute state, sorted by client and then id. Withdrawals and
+    /// adjustments can't be disputed, so their `state` is left empty.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Engine::write_accounts`].
+    pub fn write_transactions_csv<W: Write>(&self, writer: W) -> Result<(), EngineError> {
+        #[derive(serde::Serialize)]
+        struct TransactionRow {
+            client: ClientId,
+            tx: TransactionId,
+            #[serde(rename = "type")]
+            kind: &'static str,
+            amount: String,
+            state: Option<&'static str>,
+        }
+
+        let mut clients: Vec<_> = self.accounts.keys().copied().collect();
+        clients.sort_unstable();
+        let mut csv_writer = csv::WriterBuilder::new()
+            .quote_style(self.config.output_quoting)
+            .from_writer(writer);
+        for client in clients {
+            for entry in self.transaction_history(client).unwrap_or_default() {
+                let row = TransactionRow {
+                    client,
+                    tx: entry.tx,
+                    kind: match entry.kind {
+                        HistoryKind::Deposit => "deposit",
+                        HistoryKind::Withdrawal => "withdrawal",
+                        HistoryKind::Adjustment => "adjustment",
+                    },
+                    amount: format_decimal(entry.amount, OUTPUT_SCALE, self.config.zero_display),
+                    state: entry.state.map(|state| match state {
+                        PublicTxState::Normal => "normal",
+                        PublicTxState::Disputed => "disputed",
+                        PublicTxState::Resolved => "resolved",
+                        PublicTxState::ChargedBack => "charged_back",
+                    }),
+                };
+                csv_writer.serialize(row)?;
+            }
+        }
+        csv_writer.flush().map_err(EngineError::Flush)?;
+        Ok(())
+    }
+
+    /// Writes every account's balances to `writer` as a Parquet file, in the same order as
+    /// [`Engine::write_accounts`]. Balances are `Decimal128` columns at the configured
+    /// [`ColumnPrecision`], and the nullable `currency` column is only written if some account
+    /// has one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::Parquet`] if encoding or writing the file fails.
+    #[cfg(feature = "parquet")]
+    pub fn write_accounts_parquet<W: Write + Send>(&self, writer: W) -> Result<(), EngineError> {
+        use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt16Array};
+        use arrow_schema::{DataType, Field, Schema};
+        use parquet::{arrow::ArrowWriter, errors::ParquetError};
+
+        // `Decimal` has at most 29 significant digits, so any scale up to 9 fits the widest
+        // `Decimal128` precision.
+        const PRECISION: u8 = 38;
+
+        let precision = self.config.column_precision;
+        let with_currency = self.accounts.values().any(|account| account.currency.is_some());
+        let accounts = self.ordered_accounts();
+        let decimal_column = |scale: u32, value: fn(&Self, &Account) -> Decimal| -> Result<ArrayRef, ParquetError> {
+            let values = accounts.iter().map(|(_, account)| {
+                let mut value = value(self, account).round_dp(scale);
+                value.rescale(scale);
+                value.mantissa()
+            });
+            let array = Decimal128Array::from_iter_values(values)
+                .with_precision_and_scale(PRECISION, i8::try_from(scale).unwrap_or(i8::MAX))?;
+            Ok(Arc::new(array))
+        };
+
+        let mut fields = vec![
+            Field::new("client", DataType::UInt16, false),
+            Field::new(
+                "available",
+                DataType::Decimal128(PRECISION, i8::try_from(precision.available).unwrap_or(i8::MAX)),
+                false,
+            ),
+            Field::new(
+                "held",
+                DataType::Decimal128(PRECISION, i8::try_from(precision.held).unwrap_or(i8::MAX)),
+                false,
+            ),
+            Field::new(
+                "total",
+                DataType::Decimal128(PRECISION, i8::try_from(precision.total).unwrap_or(i8::MAX)),
+                false,
+            ),
+            Field::new("locked", DataType::Boolean, false),
+        ];
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt16Array::from_iter_values(
+                accounts.iter().map(|(client, _)| **client),
+            )),
+            decimal_column(precision.available, |_, account| account.available)?,
+            decimal_column(precision.held, |_, account| account.held)?,
+            decimal_column(precision.total, Self::output_total)?,
+            Arc::new(BooleanArray::from(
+                accounts.iter().map(|(_, account)| account.locked).collect::<Vec<_>>(),
+            )),
+        ];
+        if with_currency {
+            fields.push(Field::new("currency", DataType::Utf8, true));
+            columns.push(Arc::new(
+                accounts
+                    .iter()
+                    .map(|(_, account)| account.currency())
+                    .collect::<StringArray>(),
+            ));
+        }
+
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(Arc::clone(&schema), columns).map_err(ParquetError::from)?;
+        let mut parquet_writer = ArrowWriter::try_new(writer, schema, None)?;
+        parquet_writer.write(&batch)?;
+        parquet_writer.close()?;
+        Ok(())
+    }
+
+    /// Every account as a Protocol Buffers [`AccountMessage`](crate::proto::AccountMessage), in
+    /// the same order as [`Engine::write_accounts`]. Balances are decimal strings formatted
+    /// exactly as in the CSV output.
+    #[cfg(feature = "prost")]
+    #[must_use]
+    pub fn to_proto(&self) -> Vec<crate::proto::AccountMessage> {
+        let precision = self.config.column_precision;
+        self.ordered_accounts()
+            .into_iter()
+            .map(|(client, account)| crate::proto::AccountMessage {
+                client: (*client).into(),
+                available: format_decimal(account.available, precision.available, self.config.zero_display),
+                held: format_decimal(account.held, precision.held, self.config.zero_display),
+                total: format_decimal(self.output_total(account), precision.total, self.config.zero_display),
+                locked: account.locked,
+                currency: account.currency().map(str::to_owned),
+            })
+            .collect()
+    }
+
+    /// Writes [`Engine::to_proto`] to `writer` as a stream of length-delimited messages, the
+    /// framing `prost::Message::decode_length_delimited` reads back.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EngineError::Io`] if the writer fails, and [`EngineError::Flush`] if flushing it
+    /// fails.
+    #[cfg(feature = "prost")]
+    pub fn write_accounts_proto<W: Write>(&self, mut writer: W) -> Result<(), EngineError> {
+        use prost::Message;
+
+        let mut buffer = Vec::new();
+        for message in self.to_proto() {
+            buffer.clear();
+            message
+                .encode_length_delimited(&mut buffer)
+                .map_err(|error| io::Error::other(error.to_string()))?;
+            writer.write_all(&buffer)?;
+        }
+        writer.flush().map_err(EngineError::Flush)
+    }
+
+    /// Writes the accounts as CSV, reads them back and checks each row matches the in-memory
+    /// account rounded to the configured [`ColumnPrecision`], catching formatting or rounding
+    /// regressions in the output. Useful as a self-test in CI.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RoundtripError::Mismatch`] naming the first column that didn't survive,
+    /// [`RoundtripError::RowCount`] if rows went missing or were duplicated, and
+    /// [`RoundtripError::Write`] or [`RoundtripError::Read`] if the output couldn't be produced or
+    /// parsed.
+    pub fn verify_roundtrip(&self) -> Result<(), RoundtripError> {
+        // Balances are read as text and parsed exactly, rather than letting the csv layer infer a
+        // float.
+        #[derive(Deserialize)]
+        struct AccountRow {
+            client: ClientId,
+            available: String,
+            held: String,
+            total: String,
+            locked: bool,
+        }
+
+        let mut output = Vec::new();
+        self.write_accounts(&mut output).map_err(RoundtripError::Write)?;
+        let precision = self.config.column_precision;
+        let mut written = 0;
+        for row in csv::Reader::from_reader(output.as_slice()).deserialize::<AccountRow>() {
+            let row = row?;
+            written += 1;
+            let Some(account) = self.accounts.get(&row.client) else {
+                return Err(RoundtripError::RowCount {
+                    expected: self.accounts.len(),
+                    written,
+                });
+            };
+            let columns = [
+                (
+                    "available",
+                    account.available.round_dp(precision.available),
+                    &row.available,
+                ),
+                ("held", account.held.round_dp(precision.held), &row.held),
+                (
+                    "total",
+                    self.output_total(account).round_dp(precision.total),
+                    &row.total,
+                ),
+            ];
+            let mismatch = |column, expected: &dyn fmt::Display, written: &dyn fmt::Display| RoundtripError::Mismatch {
+                client: row.client,
+                column,
+                expected: expected.to_string(),
+                written: written.to_string(),
+            };
+            let mismatched = columns
+                .into_iter()
+                .find(|(_, expected, written)| Decimal::from_str(written).ok() != Some(*expected));
+            if let Some((column, expected, written)) = mismatched {
+                return Err(mismatch(column, &expected, written));
+            }
+            if account.locked != row.locked {
+                return Err(mismatch("locked", &account.locked, &row.locked));
+            }
+        }
+        if written != self.accounts.len() {
+            return Err(RoundtripError::RowCount {
+                expected: self.accounts.len(),
+                written,
+            });
+        }
+        Ok(())
+    }
+
+    // The total written for `account`, following `total_source` and `reconcile_totals`.
+    fn output_total(&self, account: &Account) -> Decimal {
+        let precision = self.config.column_precision;
+        match self.config.total_source {
+            TotalSource::Stored => account.stored_total,
+            TotalSource::Computed if self.config.reconcile_totals => {
+                account.available.round_dp(precision.available) + account.held.round_dp(precision.held)
+            }
+            TotalSource::Computed => account.total(),
+        }
+    }
+
+    fn ordered_accounts(&self) -> Vec<(&ClientId, &Account)> {
+        match self.config.output_order {
+            OutputOrder::Unspecified => self.accounts.iter().collect(),
+            OutputOrder::Sorted => {
+                let mut accounts: Vec<_> = self.accounts.iter().collect();
+                accounts.sort_unstable_by_key(|(client, _)| **client);
+                accounts
+            }
+            OutputOrder::FirstSeen => self
+                .client_order
+                .iter()
+                .filter_map(|client| self.accounts.get_key_value(client))
+                .collect(),
+        }
+    }
+
+    /// Snapshot of `client`'s account, or `None` for an unknown client.
+    #[must_use]
+    pub fn account(&self, client: ClientId) -> Option<AccountSnapshot> {
+        self.accounts.get(&client).map(AccountSnapshot::from)
+    }
+
+    /// Immutable snapshot of every account that can be shared with reader threads while the
+    /// engine keeps processing.
+    #[must_use]
+    pub fn to_account_map(&self) -> Arc<HashMap<ClientId, AccountSnapshot>> {
+        Arc::new(
+            self.accounts
+                .iter()
+                .map(|(client, account)| (*client, AccountSnapshot::from(account)))
+                .collect(),
+        )
+    }
+
+    /// How each account's balances and lock changed since the previous call, or since the engine
+    /// was created for the first call. Accounts that didn't change are left out, so a service can
+    /// publish only what's new downstream. A change too large for a [`Decimal`], such as
+    /// `available` going from the maximum to the minimum, saturates at the nearest bound.
+    pub fn take_deltas(&mut self) -> HashMap<ClientId, AccountDelta> {
+        let mut deltas = HashMap::new();
+        for (client, account) in &self.accounts {
+            let current = AccountSnapshot::from(account);
+            let (available, held, locked) = self
+                .delta_baseline
+                .get(client)
+                .map_or((Decimal::ZERO, Decimal::ZERO, false), |before| {
+                    (before.available, before.held, before.locked)
+                });
+            let delta = AccountDelta {
+                available: current.available.saturating_sub(available),
+                held: current.held.saturating_sub(held),
+                locked: (current.locked != locked).then_some(current.locked),
+            };
+            if delta.available != Decimal::ZERO || delta.held != Decimal::ZERO || delta.locked.is_some() {
+                deltas.insert(*client, delta);
+            }
+            self.delta_baseline.insert(*client, current);
+        }
+        deltas
+    }
+
+    /// Number of client accounts the engine has created.
+    #[must_use]
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Sum of every account's total balance, unrounded. Saturates at the limits of `Decimal`
+    /// rather than overflowing.
+    #[must_use]
+    pub fn system_total(&self) -> Decimal {
+        self.accounts
+            .values()
+            .map(Account::total)
+            .fold(Decimal::ZERO, Decimal::saturating_add)
+    }
+
+    /// Account counts and balance totals for a summary report, gathered in a single pass over the
+    /// accounts. Sums saturate like [`Engine::system_total`].
+    #[must_use]
+    pub fn aggregate_stats(&self) -> AggregateStats {
+        let mut stats = AggregateStats {
+            accounts: self.accounts.len(),
+            ..AggregateStats::default()
+        };
+        for account in self.accounts.values() {
+            let total = account.total();
+            stats.locked_accounts += usize::from(account.locked);
+            stats.negative_accounts += usize::from(total < Decimal::ZERO);
+            stats.total_available = stats.total_available.saturating_add(account.available);
+            stats.total_held = stats.total_held.saturating_add(account.held);
+            stats.system_total = stats.system_total.saturating_add(total);
+            stats.open_disputes += account
+                .transactions
+                .values()
+                .filter(|transaction| {
+                    matches!(
+                        transaction,
+                        Transaction::Deposit(Deposit {
+                            state: TransactionState::Disputed,
+                            ..
+                        })
+                    )
+                })
+                .count();
+        }
+        stats
+    }
+
+    /// Why `client`'s account is locked, or `None` if it isn't locked or doesn't exist.
+    #[must_use]
+    pub fn lock_reason(&self, client: ClientId) -> Option<LockReason> {
+        self.accounts.get(&client)?.lock_reason
+    }
+
+    /// Clients whose accounts are locked, in ascending order.
+    #[must_use]
+    pub fn locked_accounts(&self) -> Vec<ClientId> {
+        let mut locked: Vec<_> = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.locked)
+            .map(|(client, _)| *client)
+            .collect();
+        locked.sort_unstable();
+        locked
+    }
+
+    /// Fails with the locked client ids, in ascending order, if any account is locked. Lets a
+    /// pipeline halt once a chargeback has been seen.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ids of every locked account.
+    pub fn assert_no_locked(&self) -> Result<(), Vec<ClientId>> {
+        let locked = self.locked_accounts();
+        if locked.is_empty() {
+            Ok(())
+        } else {
+            Err(locked)
+        }
+    }
+
+    /// Clients whose total balance is negative, with their totals, most underwater first.
+    #[must_use]
+    pub fn underwater_accounts(&self) -> Vec<(ClientId, Decimal)> {
+        let mut underwater: Vec<_> = self
+            .accounts
+            .iter()
+            .map(|(client, account)| (*client, account.total()))
+            .filter(|(_, total)| *total < Decimal::ZERO)
+            .collect();
+        underwater.sort_unstable_by(|(a_client, a_total), (b_client, b_total)| {
+            a_total.cmp(b_total).then(a_client.cmp(b_client))
+        });
+        underwater
+    }
+
+    /// Fraction of `client`'s total tied up in disputes (`held / total`), or `None` for an unknown
+    /// client or a zero total.
+    #[must_use]
+    pub fn held_ratio(&self, client: ClientId) -> Option<Decimal> {
+        let account = self.accounts.get(&client)?;
+        account.held.checked_div(account.total())
+    }
+
+    /// Deposits still disputed (neither resolved nor charged back), sorted by client then
+    /// transaction id. Their funds stay held until someone follows up.
+    #[must_use]
+    pub fn open_disputes(&self) -> Vec<(ClientId, TransactionId, Decimal)> {
+        let mut disputes: Vec<_> = self
+            .accounts
+            .iter()
+            .flat_map(|(client, account)| {
+                account
+                    .transactions
+                    .iter()
+                    .filter_map(|(tx_id, transaction)| match transaction {
+                        Transaction::Deposit(Deposit {
+                            amount,
+                            state: TransactionState::Disputed,
+                            ..
+                        }) => Some((*client, *tx_id, *amount)),
+                        _ => None,
+                    })
+            })
+            .collect();
+        disputes.sort_unstable_by_key(|(client, tx_id, _)| (*client, *tx_id));
+        disputes
+    }
+
+    /// Whether a withdrawal of `amount` by `client` would currently be applied: the account exists
+    /// and is unlocked, the amount is positive, and the withdrawal fits the account policy's
+    /// balance rules and the withdrawal velocity limit. Nothing is changed.
+    #[must_use]
+    pub fn can_withdraw(&self, client: ClientId, amount: Decimal) -> bool {
+        self.accounts
+            .get(&client)
+            .is_some_and(|account| !account.locked && account.withdrawal_balance(amount).is_ok())
+            && !self.exceeds_velocity_limit(client, amount)
+    }
+
+    /// Where `client`'s deposit `tx` is in the dispute lifecycle, or `None` if the client is
+    /// unknown or `tx` isn't one of its deposits.
+    #[must_use]
+    pub fn transaction_state(&self, client: ClientId, tx: TransactionId) -> Option<PublicTxState> {
+        self.deposit_state(client, tx).map(PublicTxState::from)
+    }
+
+    /// Returns the currently disputed deposit with the largest amount for `client`, or `None` if
+    /// nothing is disputed. Ties go to the lowest transaction id.
+    #[must_use]
+    pub fn max_held_transaction(&self, client: ClientId) -> Option<(TransactionId, Decimal)> {
+        self.accounts
+            .get(&client)?
+            .transactions
+            .iter()
+            .filter_map(|(tx_id, transaction)| match transaction {
+                Transaction::Deposit(Deposit {
+                    amount,
+                    state: TransactionState::Disputed,
+                    ..
+                }) => Some((*tx_id, *amount)),
+                _ => None,
+            })
+            .max_by(|(a_tx, a_amount), (b_tx, b_amount)| a_amount.cmp(b_amount).then(b_tx.cmp(a_tx)))
+    }
+
+    /// Every transaction applied to `client`'s account, in transaction id order, or `None` for an
+    /// unknown client. Locked accounts keep their full history, including the charged-back deposit
+    /// that locked them.
+    #[must_use]
+    pub fn transaction_history(&self, client: ClientId) -> Option<Vec<HistoryEntry>> {
+        let account = self.accounts.get(&client)?;
+        let undisputed = account
+            .deposits
+            .iter()
+            .map(|(tx, amount)| (*tx, HistoryKind::Deposit, *amount, Some(PublicTxState::Normal)));
+        let others = account.transactions.iter().map(|(tx, transaction)| match transaction {
+            Transaction::Deposit(deposit) => (*tx, HistoryKind::Deposit, deposit.amount, Some(deposit.state.into())),
+            Transaction::Withdrawal(withdrawal) => (*tx, HistoryKind::Withdrawal, withdrawal.amount, None),
+            Transaction::Adjustment(adjustment) => (*tx, HistoryKind::Adjustment, adjustment.amount, None),
+        });
+        let mut history: Vec<_> = undisputed
+            .chain(others)
+            .map(|(tx, kind, amount, state)| HistoryEntry {
+                tx,
+                kind,
+                amount,
+                description: account.description(tx).map(str::to_owned),
+                state,
+            })
+            .collect();
+        history.sort_unstable_by_key(|entry| entry.tx);
+        Some(history)
+    }
+
+    /// Aggregates `client`'s applied deposits, withdrawals and disputes, or `None` for an unknown
+    /// client.
+    #[must_use]
+    pub fn client_stats(&self, client: ClientId) -> Option<ClientStats> {
+        let account = self.accounts.get(&client)?;
+        let mut stats = ClientStats {
+            deposit_count: account.deposits.len(),
+            total_deposited: account
+                .deposits
+                .iter()
+                .fold(Decimal::ZERO, |total, (_, amount)| total.saturating_add(*amount)),
+            ..ClientStats::default()
+        };
+        for transaction in account.transactions.values() {
+            match transaction {
+                // Deposits only leave `deposits` once disputed.
+                Transaction::Deposit(deposit) => {
+                    stats.deposit_count += 1;
+                    stats.total_deposited = stats.total_deposited.saturating_add(deposit.amount);
+                    stats.dispute_count += 1;
+                }
+                Transaction::Withdrawal(withdrawal) => {
+                    stats.withdrawal_count += 1;
+                    stats.total_withdrawn = stats.total_withdrawn.saturating_add(withdrawal.amount);
+                }
+                Transaction::Adjustment(_) => {}
+            }
+        }
+        Some(stats)
+    }
+
+    /// Clients that repeatedly withdraw a deposit in full right after making it, by client id, as
+    /// configured by [`Engine::with_suspicious_pattern_thresholds`]. Each withdrawal is paired with
+    /// the latest earlier deposit it covers that isn't already paired.
+    #[must_use]
+    pub fn suspicious_patterns(&self) -> Vec<SuspiciousPattern> {
+        let thresholds = self.config.suspicious_thresholds;
+        let mut patterns: Vec<_> = self
+            .accounts
+            .keys()
+            .filter_map(|client| {
+                let mut open_deposits = Vec::new();
+                let mut cycles = Vec::new();
+                for entry in self.transaction_history(*client)? {
+                    match entry.kind {
+                        HistoryKind::Deposit if entry.amount >= thresholds.min_amount => {
+                            open_deposits.push((entry.tx, entry.amount));
+                        }
+                        HistoryKind::Withdrawal => {
+                            let emptied = open_deposits.iter().rposition(|(deposit, amount)| {
+                                *amount <= entry.amount && entry.tx - deposit <= thresholds.max_tx_gap
+                            });
+                            if let Some(index) = emptied {
+                                cycles.push((open_deposits.remove(index).0, entry.tx));
+                            }
+                        }
+                        HistoryKind::Deposit | HistoryKind::Adjustment => {}
+                    }
+                }
+                (!cycles.is_empty() && cycles.len() >= thresholds.min_cycles).then_some(
+                    SuspiciousPattern::RapidWithdrawal {
+                        client: *client,
+                        cycles,
+                    },
+                )
+            })
+            .collect();
+        patterns.sort_unstable_by_key(|SuspiciousPattern::RapidWithdrawal { client, .. }| *client);
+        patterns
+    }
+
+    /// Deposits `amount` into `client`'s account, returning why it wasn't applied as an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TransactionError`] describing why the deposit was skipped.
+    pub fn try_deposit(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    ) -> Result<(), TransactionError> {
+        outcome_result(self.apply(InputTransaction::Deposit(TransactionIds { client, tx }, amount)))
+    }
+
+    /// Withdraws `amount` from `client`'s account, returning why it wasn't applied as an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TransactionError`] describing why the withdrawal was skipped.
+    pub fn try_withdraw(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    ) -> Result<(), TransactionError> {
+        outcome_result(self.apply(InputTransaction::Withdrawal(TransactionIds { client, tx }, amount)))
+    }
+
+    /// Deposits `amount` and immediately disputes it, as if both rows had been read in turn.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`TransactionError`] of the first step that wasn't applied. Earlier steps stay
+    /// applied.
+    pub fn apply_and_dispute(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    ) -> Result<(), TransactionError> {
+        self.try_deposit(client, tx, amount)?;
+        outcome_result(self.apply(InputTransaction::Dispute(TransactionIds { client, tx })))
+    }
+
+    /// Deposits `amount`, disputes it and resolves the dispute.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`TransactionError`] of the first step that wasn't applied. Earlier steps stay
+    /// applied.
+    pub fn apply_and_resolve(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    ) -> Result<(), TransactionError> {
+        self.apply_and_dispute(client, tx, amount)?;
+        outcome_result(self.apply(InputTransaction::Resolve(TransactionIds { client, tx })))
+    }
+
+    /// Deposits `amount`, disputes it and charges it back, locking the account.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`TransactionError`] of the first step that wasn't applied. Earlier steps stay
+    /// applied.
+    pub fn apply_and_chargeback(
+        &mut self,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+    ) -> Result<(), TransactionError> {
+        self.apply_and_dispute(client, tx, amount)?;
+        outcome_result(self.apply(InputTransaction::Chargeback(TransactionIds { client, tx })))
+    }
+
+    fn parse_transaction(&self, mut raw: RawInputTransaction) -> Result<InputTransaction, EngineError> {
+        if self.config.split_amount_columns {
+            raw.amount = match (raw.amount_whole.take(), raw.amount_frac.take()) {
+                (Some(whole), Some(frac)) if frac.bytes().all(|digit| digit.is_ascii_digit()) => {
+                    Some(format!("{whole}.{frac}"))
+                }
+                (Some(whole), Some(frac)) => {
+                    return Err(EngineError::InvalidAmount(format!(
+                        "{whole}.{frac} (tx {}) has a non-numeric fraction",
+                        raw.tx
+                    )))
+                }
+                (whole, None) => whole,
+                (None, Some(frac)) => Some(format!("0.{frac}")),
+            };
+        }
+        if self.config.missing_amount_policy == MissingAmountPolicy::TreatAsZero {
+            raw.amount.get_or_insert_with(|| "0".to_string());
+        }
+        if self.config.lenient_amount_parsing {
+            if self.config.decimal_comma {
+                if let Some(amount) = raw.amount.as_mut().filter(|amount| !amount.contains('.')) {
+                    *amount = amount.replacen(',', ".", 1);
+                }
+            }
+            if let Some(expanded) = raw.amount.as_deref().and_then(expand_scientific) {
+                raw.amount = Some(expanded);
+            }
+        }
+        let mut transaction = InputTransaction::try_from(raw)?;
+        if self.config.negative_deposit_as_withdrawal {
+            if let InputTransaction::Deposit(ids, amount) = transaction {
+                if amount.is_sign_negative() {
+                    transaction = InputTransaction::Withdrawal(ids, amount.abs());
+                }
+            }
+        }
+        if let (Some(max_scale), Some(amount)) = (self.config.max_input_scale, transaction.amount()) {
+            if amount.scale() > max_scale {
+                return Err(EngineError::InvalidAmount(format!(
+                    "{amount} (tx {}) has more than {max_scale} decimal places",
+                    transaction.ids().tx
+                )));
+            }
+        }
+        Ok(transaction)
+    }
+
+    /// Gives `client` an opening `available` balance, for tests or migrations from another system,
+    /// opening the account if needed. It's recorded as the account's
+    /// [`AccountSnapshot::opening_balance`] rather than as a deposit, so it can't be disputed.
+    /// Setting it again replaces it, as long as the client still has no transactions.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransactionError::HasTransactions`] if transactions were already applied to the
+    /// client, [`TransactionError::Locked`] for a locked account, and
+    /// [`TransactionError::BalanceOverflow`] if the balance doesn't fit alongside `held`.
+    pub fn set_opening_balance(&mut self, client: ClientId, available: Decimal) -> Result<(), TransactionError> {
+        if let Some(account) = self.accounts.get(&client) {
+            if account.transaction_count() > 0 {
+                return Err(TransactionError::HasTransactions);
+            }
+            if account.locked {
+                return Err(TransactionError::Locked);
+            }
+        }
+        let account = match self.accounts.entry(client) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.client_order.push(client);
+                entry.insert(Account::with_policy(self.config.account_policy))
+            }
+        };
+        outcome_result(account.set_opening_balance(available))
+    }
+
+    /// Admin operation reversing an erroneous chargeback of deposit `tx`. The amount is restored to
+    /// `available`, the deposit becomes resolved (so it can't be disputed again), and the account
+    /// is unlocked if that chargeback is what locked it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TransactionError::UnknownClient`] for an unknown client,
+    /// [`TransactionError::NotApplicable`] if `tx` isn't one of the client's deposits, and
+    /// [`TransactionError::InvalidTransition`] if it isn't charged back.
+    pub fn reverse_chargeback(&mut self, client: ClientId, tx: TransactionId) -> Result<(), TransactionError> {
+        let account = self.accounts.get_mut(&client).ok_or(TransactionError::UnknownClient)?;
+        outcome_result(account.reverse_chargeback(tx))
+    }
+
+    /// Applies `transactions` in order as one batch and returns each one's outcome, in the same
+    /// order. A dispute right after its deposit in the batch sees that deposit, just like
+    /// consecutive rows of a file. With [`Engine::with_dispute_buffering`], a dispute arriving
+    /// before its deposit is held back and retried once the rest of the batch is applied.
+    pub fn apply_batch(&mut self, transactions: impl IntoIterator<Item = InputTransaction>) -> Vec<TransactionOutcome> {
+        self.withdrawal_velocity.clear();
+        let mut outcomes = Vec::new();
+        let mut orphan_disputes = Vec::new();
+        for transaction in transactions {
+            if self.is_orphan_dispute(&transaction) {
+                orphan_disputes.push((outcomes.len(), transaction));
+                // Placeholder until the retry below.
+                outcomes.push(TransactionOutcome::Ignored);
+                continue;
+            }
+            outcomes.push(self.process_record(transaction));
+        }
+        for (index, dispute) in orphan_disputes {
+            outcomes[index] = self.process_record(dispute);
+        }
+        outcomes
+    }
+
+    // A dispute of a transaction id nothing has claimed yet, which dispute buffering holds back
+    // until the end of the batch in case its deposit is further on.
+    fn is_orphan_dispute(&self, transaction: &InputTransaction) -> bool {
+        self.config.dispute_buffering
+            && matches!(transaction, InputTransaction::Dispute(_))
+            && !self.is_claimed(transaction.ids().tx)
+    }
+
+    // The records are only kept when rows are being collected.
+    fn retry_orphan_disputes(
+        &mut self,
+        orphan_disputes: Vec<(InputTransaction, RowMetadata, Option<csv::StringRecord>)>,
+        mut errors: Option<&mut Vec<RowError>>,
+    ) {
+        for (dispute, metadata, record) in orphan_disputes {
+            let outcome = self.process_record_with_metadata(dispute, &metadata);
+            if let (Some(errors), Some(record), Err(err)) = (errors.as_deref_mut(), record, outcome_result(outcome)) {
+                errors.push(RowError::skipped(err, dispute, &metadata, &record));
+            }
+        }
+    }
+
+    /// Applies a single transaction and reports what happened to it.
+    pub fn apply(&mut self, transaction: InputTransaction) -> TransactionOutcome {
+        self.process_record(transaction)
+    }
+
+    fn process_record(&mut self, input_transaction: InputTransaction) -> TransactionOutcome {
+        self.process_record_with_metadata(input_transaction, &RowMetadata::default())
+    }
+
+    // An account adopts the currency of its first deposit naming one, and later transactions naming
+    // another currency are skipped. Descriptions are stored for the transaction's history.
+    fn process_record_with_metadata(
+        &mut self,
+        input_transaction: InputTransaction,
+        metadata: &RowMetadata,
+    ) -> TransactionOutcome {
+        let span = self.config.per_transaction_spans.then(|| {
+            let TransactionIds { client, tx } = input_transaction.ids();
+            info_span!("transaction", client, tx, kind = input_transaction.kind())
+        });
+        let _entered = span.as_ref().map(tracing::Span::enter);
+
+        let TransactionIds { client, tx } = input_transaction.ids();
+        if self.coalesce_dispute_step(input_transaction) {
+            return TransactionOutcome::Ignored;
+        }
+        let undo = (self.config.undo_depth > 0).then(|| UndoEntry {
+            client,
+            tx,
+            checkpoint: self.accounts.get(&client).map(|account| account.checkpoint(tx)),
+            claimed_id: !self.is_claimed(tx),
+            input_checksum: self.input_checksum,
+            summary: self.summary.clone(),
+            velocity: self.withdrawal_velocity.get(&client).copied(),
+            dispute_opened_at: self.dispute_opened_at.get(&(client, tx)).copied(),
+            latest_timestamp: self.latest_timestamp,
+        });
+        let currency = metadata.currency.as_deref();
+        let account_currency = self.accounts.get(&client).and_then(Account::currency);
+        let mismatch = matches!((currency, account_currency), (Some(a), Some(b)) if a != b);
+        let reserved = self
+            .config
+            .reserved_clients
+            .as_ref()
+            .is_some_and(|reserved| reserved.contains(&client));
+        let outcome = match input_transaction {
+            _ if reserved => {
+                warn!(client, "Skipping transaction for a reserved client");
+                TransactionOutcome::SkippedReservedClient
+            }
+            _ if mismatch => {
+                warn!(
+                    client,
+                    currency, account_currency, "Skipping transaction in another currency"
+                );
+                TransactionOutcome::SkippedCurrencyMismatch
+            }
+            InputTransaction::Deposit(TransactionIds { client, tx }, amount) => self.deposit(client, tx, amount),
+            InputTransaction::Withdrawal(TransactionIds { client, tx }, amount) => self.withdraw(client, tx, amount),
+            InputTransaction::Dispute(TransactionIds { client, tx }) => self.dispute(client, tx),
+            InputTransaction::Resolve(TransactionIds { client, tx }) => self.resolve(client, tx),
+            InputTransaction::Chargeback(TransactionIds { client, tx }) => self.chargeback(client, tx),
+            InputTransaction::Adjustment(TransactionIds { client, tx }, amount) => self.adjust(client, tx, amount),
+        };
+        let outcome = self.classify_transition(input_transaction, outcome);
+        if let (TransactionOutcome::Applied, Some(account)) = (outcome, self.accounts.get_mut(&client)) {
+            if let (InputTransaction::Deposit(..), Some(currency)) = (input_transaction, currency) {
+                account.currency.get_or_insert_with(|| currency.to_owned());
+            }
+            if let (Some(_), Some(description)) = (input_transaction.amount(), &metadata.description) {
+                account.set_description(tx, description.clone());
+            }
+            #[cfg(debug_assertions)]
+            {
+                let peak = self.balance_peaks.entry(client).or_default();
+                *peak = [account.available, account.held, account.stored_total]
+                    .into_iter()
+                    .fold(*peak, |peak, balance| peak.max(balance.abs()));
+                debug_assert!(
+                    self.config.epsilon_clamp.is_some() || totals_agree(account.stored_total, account.total(), *peak),
+                    "client {client} stored total {} diverged from available + held {}",
+                    account.stored_total,
+                    account.total()
+                );
+            }
+            if let Some(epsilon) = self.config.epsilon_clamp {
+                for balance in [&mut account.available, &mut account.held] {
+                    if balance.is_sign_negative() && -*balance <= epsilon {
+                        *balance = Decimal::ZERO;
+                    }
+                }
+            }
+        }
+        self.summary.record(outcome);
+        self.track_dispute_age(input_transaction, outcome, metadata.timestamp);
+        self.remember_dispute_step(input_transaction, outcome);
+        if outcome == TransactionOutcome::Applied {
+            self.input_checksum = chain_checksum(self.input_checksum, &input_transaction);
+            if let Some(mut undo) = undo {
+                undo.summary = self.summary.since(&undo.summary);
+                if self.undo_log.len() == self.config.undo_depth {
+                    self.undo_log.pop_front();
+                }
+                self.undo_log.push_back(undo);
+            }
+            if let Err(err) = self.audit.record(input_transaction) {
+                warn!(error = %err, "Failed to write audit event");
+                self.summary.audit_write_errors += 1;
+            }
+        }
+        outcome
+    }
+
+    // Remembers when applied disputes were raised and drops settled ones, for auto-resolving.
+    // Every row's timestamp counts towards "now", applied or not.
+    fn track_dispute_age(
+        &mut self,
+        input_transaction: InputTransaction,
+        outcome: TransactionOutcome,
+        timestamp: Option<u64>,
+    ) {
+        if self.config.dispute_auto_resolve.is_none() {
+            return;
+        }
+        self.latest_timestamp = self.latest_timestamp.max(timestamp);
+        if outcome != TransactionOutcome::Applied {
+            return;
+        }
+        let TransactionIds { client, tx } = input_transaction.ids();
+        match (input_transaction, timestamp) {
+            (InputTransaction::Dispute(_), Some(timestamp)) => {
+                self.dispute_opened_at.insert((client, tx), timestamp);
+            }
+            (InputTransaction::Resolve(_) | InputTransaction::Chargeback(_), _) => {
+                self.dispute_opened_at.remove(&(client, tx));
+            }
+            _ => {}
+        }
+    }
+
+    fn auto_resolve_stale_disputes(&mut self) {
+        let (Some(after), Some(now)) = (self.config.dispute_auto_resolve, self.latest_timestamp) else {
+            return;
+        };
+        let mut stale: Vec<_> = self
+            .dispute_opened_at
+            .iter()
+            .filter(|(_, opened_at)| now.saturating_sub(**opened_at) > after.as_secs())
+            .map(|(ids, _)| *ids)
+            .collect();
+        stale.sort_unstable();
+        for (client, tx) in stale {
+            self.dispute_opened_at.remove(&(client, tx));
+            if self.resolve(client, tx) == TransactionOutcome::Applied {
+                tracing::info!(client, tx, "Auto-resolved stale dispute");
+                self.summary.auto_resolved_disputes += 1;
+            }
+        }
+    }
+
+    // Whether `input_transaction` repeats a dispute step applied within the coalescing window to a
+    // deposit that hasn't changed state since, in which case it's counted and should be dropped.
+    // Seeing a step again restarts its window.
+    fn coalesce_dispute_step(&mut self, input_transaction: InputTransaction) -> bool {
+        let window = self.config.dispute_coalesce_window as u64;
+        if window == 0 || self.config.strict_state_transitions {
+            return false;
+        }
+        self.processed_count += 1;
+        let now = self.processed_count;
+        self.recent_dispute_steps.retain(|step| now - step.seen_at <= window);
+        if !matches!(
+            input_transaction,
+            InputTransaction::Dispute(_) | InputTransaction::Resolve(_) | InputTransaction::Chargeback(_)
+        ) {
+            return false;
+        }
+
+        let kind = input_transaction.kind();
+        let TransactionIds { client, tx } = input_transaction.ids();
+        let state = self.deposit_state(client, tx);
+        let Some(step) = self
+            .recent_dispute_steps
+            .iter_mut()
+            .find(|step| step.kind == kind && step.client == client && step.tx == tx && Some(step.state) == state)
+        else {
+            return false;
+        };
+        if !step.logged {
+            warn!(client, tx, kind, window, "Coalescing repeated dispute step");
+            step.logged = true;
+        }
+        step.seen_at = now;
+        self.summary.coalesced_dispute_steps += 1;
+        true
+    }
+
+    // Records an applied dispute step, with the deposit state it left behind, so repeats of it can
+    // be coalesced.
+    fn remember_dispute_step(&mut self, input_transaction: InputTransaction, outcome: TransactionOutcome) {
+        if self.config.dispute_coalesce_window == 0
+            || self.config.strict_state_transitions
+            || outcome != TransactionOutcome::Applied
+        {
+            return;
+        }
+        let (InputTransaction::Dispute(TransactionIds { client, tx })
+        | InputTransaction::Resolve(TransactionIds { client, tx })
+        | InputTransaction::Chargeback(TransactionIds { client, tx })) = input_transaction
+        else {
+            return;
+        };
+        let Some(state) = self.deposit_state(client, tx) else {
+            return;
+        };
+        let kind = input_transaction.kind();
+        self.recent_dispute_steps
+            .retain(|step| !(step.kind == kind && step.client == client && step.tx == tx));
+        self.recent_dispute_steps.push(RecentDisputeStep {
+            kind,
+            client,
+            tx,
+            state,
+            seen_at: self.processed_count,
+            logged: false,
+        });
+    }
+
+    // Dispute steps against an already charged-back deposit are always counted for fraud analytics.
+    // The ledger checks the lock before the deposit's state, so in strict mode they're also
+    // re-classified here as invalid transitions.
+    fn classify_transition(
+        &mut self,
+        input_transaction: InputTransaction,
+        outcome: TransactionOutcome,
+    ) -> TransactionOutcome {
+        let (InputTransaction::Dispute(TransactionIds { client, tx })
+        | InputTransaction::Resolve(TransactionIds { client, tx })
+        | InputTransaction::Chargeback(TransactionIds { client, tx })) = input_transaction
+        else {
+            return outcome;
+        };
+        let after_chargeback = outcome != TransactionOutcome::Applied
+            && matches!(self.deposit_state(client, tx), Some(TransactionState::ChargedBack));
+        if after_chargeback {
+            warn!(
+                client,
+                tx,
+                kind = input_transaction.kind(),
+                "Dispute step against a charged-back transaction"
+            );
+            self.summary.post_chargeback_attempts += 1;
+        }
+        let repeated_dispute = outcome != TransactionOutcome::Applied
+            && matches!(input_transaction, InputTransaction::Dispute(_))
+            && matches!(self.deposit_state(client, tx), Some(TransactionState::Disputed));
+        if repeated_dispute {
+            warn!(client, tx, "Dispute of a transaction that is already disputed");
+            self.summary.duplicate_dispute_attempts += 1;
+        }
+        let before_dispute = self.config.track_lifecycle_order
+            && !matches!(input_transaction, InputTransaction::Dispute(_))
+            && matches!(self.deposit_state(client, tx), Some(TransactionState::Normal));
+        if before_dispute {
+            warn!(
+                client,
+                tx,
+                kind = input_transaction.kind(),
+                "Dispute step arrived before its dispute"
+            );
+            self.summary.out_of_order_lifecycle += 1;
+        }
+        if !self.config.strict_state_transitions {
+            return if outcome == TransactionOutcome::InvalidTransition {
+                TransactionOutcome::Ignored
+            } else {
+                outcome
+            };
+        }
+
+        if outcome == TransactionOutcome::Ignored && !self.accounts.contains_key(&client) {
+            warn!(
+                client,
+                tx,
+                kind = input_transaction.kind(),
+                "Dispute step for a client that doesn't exist"
+            );
+            return TransactionOutcome::UnknownClient;
+        }
+        if outcome == TransactionOutcome::InvalidTransition || after_chargeback {
+            warn!(
+                client,
+                tx,
+                kind = input_transaction.kind(),
+                "Invalid dispute state transition"
+            );
+            return TransactionOutcome::InvalidTransition;
+        }
+        outcome
+    }
+
+    fn deposit_state(&self, client_id: ClientId, tx_id: TransactionId) -> Option<TransactionState> {
+        self.accounts.get(&client_id)?.deposit_state(tx_id)
+    }
+
+    fn deposit(&mut self, client_id: ClientId, tx_id: TransactionId, amount: Decimal) -> TransactionOutcome {
+        let account = match self.get_unlocked_account_or_default(client_id, tx_id) {
+            Ok(account) => account,
+            Err(TransactionOutcome::SkippedDuplicate) => {
+                self.report_duplicate_deposit(client_id, tx_id);
+                return TransactionOutcome::SkippedDuplicate;
+            }
+            Err(outcome) => return outcome,
+        };
+        let outcome = account.deposit(tx_id, amount);
+        self.claim_id(tx_id);
+        outcome
+    }
+
+    // A deposit reusing a charged-back deposit's id is more likely an upstream id collision hiding a
+    // real new deposit than a replayed row, so it's called out separately.
+    fn report_duplicate_deposit(&mut self, client_id: ClientId, tx_id: TransactionId) {
+        let reuses_charged_back = self
+            .accounts
+            .values()
+            .find_map(|account| account.transactions.get(&tx_id))
+            .is_some_and(|transaction| {
+                matches!(
+                    transaction,
+                    Transaction::Deposit(Deposit {
+                        state: TransactionState::ChargedBack,
+                        ..
+                    })
+                )
+            });
+        if reuses_charged_back {
+            warn!(
+                client = client_id,
+                tx = tx_id,
+                "Deposit reuses the id of a charged-back deposit"
+            );
+            if self.config.strict_state_transitions {
+                self.summary.charged_back_id_reuse += 1;
+            }
+        } else {
+            warn!(
+                client = client_id,
+                tx = tx_id,
+                "Deposit duplicates an existing transaction id"
+            );
+        }
+    }
+
+    fn withdraw(&mut self, client_id: ClientId, tx_id: TransactionId, amount: Decimal) -> TransactionOutcome {
+        let velocity = self.withdrawal_velocity.get(&client_id).copied().unwrap_or_default();
+        let over_limit = self.exceeds_velocity_limit(client_id, amount);
+        let freeze = self.config.freeze_on_velocity_limit;
+        let account = match self.get_unlocked_account_or_default(client_id, tx_id) {
+            Ok(account) => account,
+            Err(outcome) => return outcome,
+        };
+        let outcome = if over_limit {
+            warn!(
+                client = client_id,
+                tx = tx_id,
+                "Skipping withdrawal over the velocity limit"
+            );
+            if freeze {
+                account.locked = true;
+                account.lock_reason = Some(LockReason::VelocityLimit);
+            }
+            TransactionOutcome::SkippedVelocityLimit
+        } else {
+            account.withdraw(tx_id, amount)
+        };
+        // Insert transaction even if withdrawal has insufficient funds.
+        self.claim_id(tx_id);
+        if outcome == TransactionOutcome::Applied && self.config.withdrawal_velocity_limit.is_some() {
+            self.withdrawal_velocity
+                .insert(client_id, (velocity.0 + 1, velocity.1.saturating_add(amount)));
+        }
+        outcome
+    }
+
+    fn exceeds_velocity_limit(&self, client_id: ClientId, amount: Decimal) -> bool {
+        let (count, total) = self.withdrawal_velocity.get(&client_id).copied().unwrap_or_default();
+        self.config
+            .withdrawal_velocity_limit
+            .is_some_and(|(max_count, max_amount)| {
+                count >= max_count || total.checked_add(amount).is_none_or(|total| total > max_amount)
+            })
+    }
+
+    fn adjust(&mut self, client_id: ClientId, tx_id: TransactionId, amount: Decimal) -> TransactionOutcome {
+        let account = match self.get_unlocked_account_or_default(client_id, tx_id) {
+            Ok(account) => account,
+            Err(outcome) => return outcome,
+        };
+        let outcome = account.adjust(tx_id, amount);
+        self.claim_id(tx_id);
+        outcome
+    }
+
+    fn dispute(&mut self, client_id: ClientId, tx_id: TransactionId) -> TransactionOutcome {
+        self.accounts
+            .get_mut(&client_id)
+            .map_or(TransactionOutcome::Ignored, |account| account.dispute(tx_id))
+    }
+
+    fn resolve(&mut self, client_id: ClientId, tx_id: TransactionId) -> TransactionOutcome {
+        self.accounts
+            .get_mut(&client_id)
+            .map_or(TransactionOutcome::Ignored, |account| account.resolve(tx_id))
+    }
+
+    fn chargeback(&mut self, client_id: ClientId, tx_id: TransactionId) -> TransactionOutcome {
+        self.accounts
+            .get_mut(&client_id)
+            .map_or(TransactionOutcome::Ignored, |account| account.chargeback(tx_id))
+    }
+
+    fn apply_account_policy(&mut self) {
+        for account in self.accounts.values_mut() {
+            account.policy = self.config.account_policy;
+        }
+    }
+
+    // Cross-client duplicate detection lives here rather than in the ledger, since tx ids are unique
+    // across the whole input, not just per account.
+    fn get_unlocked_account_or_default(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Result<&mut Account, TransactionOutcome> {
+        let claimed = self.is_claimed(tx_id);
+        let at_limit = self.config.max_clients.is_some_and(|max| self.accounts.len() >= max);
+        if at_limit && !self.accounts.contains_key(&client_id) {
+            warn!(
+                client = client_id,
+                "Skipping transaction for a new client beyond the client limit"
+            );
+            return Err(TransactionOutcome::SkippedClientLimit);
+        }
+        let account = match self.accounts.entry(client_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.client_order.push(client_id);
+                entry.insert(Account::with_policy(self.config.account_policy))
+            }
+        };
+        if account.locked {
+            return Err(TransactionOutcome::SkippedLocked);
+        }
+        if claimed {
+            return Err(TransactionOutcome::SkippedDuplicate);
+        }
+        Ok(account)
+    }
+
+    // Runs on the sharded reading thread, which owns the claimed ids and the set of clients with
+    // accounts, so both checks hold across shards. Returns the outcome a deposit, withdrawal or
+    // adjustment is skipped with, or `None` to hand the row to its worker.
+    fn admit_sharded_row(
+        &mut self,
+        transaction: InputTransaction,
+        known_clients: &mut HashSet<ClientId>,
+    ) -> Option<TransactionOutcome> {
+        let (InputTransaction::Deposit(TransactionIds { client, tx }, _)
+        | InputTransaction::Withdrawal(TransactionIds { client, tx }, _)
+        | InputTransaction::Adjustment(TransactionIds { client, tx }, _)) = transaction
+        else {
+            return None;
+        };
+        // Reserved clients never get an account; their worker skips them.
+        if self
+            .config
+            .reserved_clients
+            .as_ref()
+            .is_some_and(|reserved| reserved.contains(&client))
+        {
+            return None;
+        }
+        let at_limit = self.config.max_clients.is_some_and(|max| known_clients.len() >= max);
+        let outcome = if at_limit && !known_clients.contains(&client) {
+            warn!(client, "Skipping transaction for a new client beyond the client limit");
+            TransactionOutcome::SkippedClientLimit
+        } else if self.is_claimed(tx) {
+            warn!(client, tx, "Transaction duplicates an existing transaction id");
+            TransactionOutcome::SkippedDuplicate
+        } else {
+            known_clients.insert(client);
+            self.claim_id(tx);
+            return None;
+        };
+        self.summary.record(outcome);
+        Some(outcome)
+    }
+
+    fn claim_id(&mut self, tx_id: TransactionId) {
+        if self.ids_claimed_upstream {
+            return;
+        }
+        if self.config.bloom_dedup {
+            let (expected_ids, false_positive_rate) = self.config.bloom_sizing.unwrap_or(DEFAULT_BLOOM_SIZING);
+            self.bloom_ids
+                .get_or_insert_with(|| BloomFilter::new(expected_ids, false_positive_rate))
+                .insert(tx_id);
+        } else {
+            self.transaction_ids_processed.insert(tx_id);
+        }
+    }
+
+    fn is_claimed(&self, tx_id: TransactionId) -> bool {
+        if self.ids_claimed_upstream {
+            return false;
+        }
+        if self.config.bloom_dedup {
+            self.bloom_ids.as_ref().is_some_and(|bloom| bloom.contains(tx_id))
+        } else {
+            self.transaction_ids_processed.contains(&tx_id)
+        }
+    }
+}
+
+// Summarizes rather than deriving, since a derived impl would dump every transaction.
+impl fmt::Debug for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Engine")
+            .field("accounts", &self.accounts.len())
+            .field(
+                "transactions",
+                &self.accounts.values().map(Account::transaction_count).sum::<usize>(),
+            )
+            .field(
+                "locked",
+                &self.accounts.values().filter(|account| account.locked).count(),
+            )
+            .field("system_total", &self.system_total())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Deref for Finalized {
+    type Target = Engine;
+
+    fn deref(&self) -> &Self::Target {
+        &self.engine
+    }
+}
+
+impl From<&Account> for AccountSnapshot {
+    fn from(account: &Account) -> Self {
+        Self {
+            available: account.available,
+            held: account.held,
+            total: account.total(),
+            locked: account.locked,
+            lifetime_deposited: account.lifetime_deposited,
+            lifetime_withdrawn: account.lifetime_withdrawn,
+            opening_balance: account.opening_balance,
+        }
+    }
+}
+
+impl InputTransaction {
+    #[must_use]
+    pub const fn ids(&self) -> TransactionIds {
+        match self {
+            Self::Deposit(ids, _)
+            | Self::Withdrawal(ids, _)
+            | Self::Adjustment(ids, _)
+            | Self::Dispute(ids)
+            | Self::Resolve(ids)
+            | Self::Chargeback(ids) => *ids,
+        }
+    }
+
+    /// The amount for deposits, withdrawals and adjustments.
+    #[must_use]
+    pub const fn amount(&self) -> Option<Decimal> {
+        match self {
+            Self::Deposit(_, amount) | Self::Withdrawal(_, amount) | Self::Adjustment(_, amount) => Some(*amount),
+            Self::Dispute(_) | Self::Resolve(_) | Self::Chargeback(_) => None,
+        }
+    }
+
+    /// The input `type` this transaction was parsed from.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Deposit(..) => "deposit",
+            Self::Withdrawal(..) => "withdrawal",
+            Self::Dispute(_) => "dispute",
+            Self::Resolve(_) => "resolve",
+            Self::Chargeback(_) => "chargeback",
+            Self::Adjustment(..) => "adjustment",
+        }
+    }
+}
+
+impl TryFrom<RawInputTransaction> for InputTransaction {
+    type Error = EngineError;
+    fn try_from(raw: RawInputTransaction) -> Result<Self, Self::Error> {
+        let RawInputTransaction {
+            tx_type,
+            client,
+            tx,
+            amount,
+            direction,
+            ..
+        } = raw;
+        let ids = TransactionIds { client, tx };
+        let get_amount = || {
+            let amount = amount
+                .as_deref()
+                .ok_or_else(|| EngineError::InputValidation(format!("{tx_type} (tx {tx}) missing amount")))?;
+            Decimal::from_str(amount).map_err(|_| {
+                EngineError::InvalidAmount(if amount.contains(',') {
+                    format!(
+                        "{amount} (tx {tx}) uses a comma separator, which is ambiguous across locales; amounts \
+                         need a `.` decimal separator unless lenient parsing with a decimal comma is enabled"
+                    )
+                } else {
+                    format!("{amount} (tx {tx}) is not a decimal number")
+                })
+            })
+        };
+
+        match tx_type.as_str() {
+            "deposit" => Ok(Self::Deposit(ids, get_amount()?)),
+            "withdrawal" => Ok(Self::Withdrawal(ids, get_amount()?)),
+            "dispute" => Ok(Self::Dispute(ids)),
+            "resolve" => Ok(Self::Resolve(ids)),
+            "chargeback" => Ok(Self::Chargeback(ids)),
+            "adjustment" => Ok(Self::Adjustment(ids, get_amount()?)),
+            "movement" => match direction.as_deref() {
+                Some("credit") => Ok(Self::Deposit(ids, get_amount()?)),
+                Some("debit") => Ok(Self::Withdrawal(ids, get_amount()?)),
+                Some(direction) => Err(EngineError::InputValidation(format!(
+                    "movement (tx {tx}) has unknown direction: {direction}"
+                ))),
+                None => Err(EngineError::InputValidation(format!(
+                    "movement (tx {tx}) missing direction"
+                ))),
+            },
+            _ => Err(EngineError::InputValidation(format!(
+                "Unknown transaction type: {tx_type}"
+            ))),
+        }
+    }
+}
+
+const fn outcome_result(outcome: TransactionOutcome) -> Result<(), TransactionError> {
+    match outcome {
+        TransactionOutcome::Applied => Ok(()),
+        TransactionOutcome::SkippedLocked => Err(TransactionError::Locked),
+        TransactionOutcome::SkippedDuplicate => Err(TransactionError::Duplicate),
+        TransactionOutcome::SkippedInsufficientFunds => Err(TransactionError::InsufficientFunds),
+        TransactionOutcome::SkippedInvalidAmount => Err(TransactionError::InvalidAmount),
+        TransactionOutcome::SkippedBalanceBounds => Err(TransactionError::BalanceBounds),
+        TransactionOutcome::SkippedBalanceOverflow => Err(TransactionError::BalanceOverflow),
+        TransactionOutcome::SkippedOpenDispute => Err(TransactionError::OpenDispute),
+        TransactionOutcome::SkippedCurrencyMismatch => Err(TransactionError::CurrencyMismatch),
+        TransactionOutcome::SkippedReservedClient => Err(TransactionError::ReservedClient),
+        TransactionOutcome::SkippedClientLimit => Err(TransactionError::ClientLimit),
+        TransactionOutcome::SkippedVelocityLimit => Err(TransactionError::VelocityLimit),
+        TransactionOutcome::Ignored => Err(TransactionError::NotApplicable),
+        TransactionOutcome::InvalidTransition => Err(TransactionError::InvalidTransition),
+        TransactionOutcome::UnknownClient => Err(TransactionError::UnknownClient),
+    }
+}
+
+// Minimal glob matching on a single file name: `*` matches any run of characters and `?` exactly
+// one. No character classes or escaping.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` seen, and the name position it's currently matched up to.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Opens an input file, decompressing it as it's read if its extension names a supported codec.
+fn open_input(path: &Path) -> Result<Box<dyn Read>, EngineError> {
+    let file = File::open(path).map_err(|error| EngineError::OpenFile {
+        path: path.to_path_buf(),
+        file_error: error,
+    })?;
+    Ok(match path.extension().and_then(OsStr::to_str) {
+        #[cfg(feature = "bzip2")]
+        Some("bz2") => Box::new(bzip2::read::BzDecoder::new(file)),
+        #[cfg(feature = "zstd")]
+        Some("zst") => Box::new(zstd::stream::read::Decoder::new(file)?),
+        _ => Box::new(file),
+    })
+}
+
+// Surfaces a failure of the underlying reader as `EngineError::Io`, so callers can tell it apart
+// from a malformed row.
+fn reader_error(err: csv::Error) -> EngineError {
+    if !err.is_io_error() {
+        return EngineError::Csv(err);
+    }
+    let csv::ErrorKind::Io(io_error) = err.into_kind() else {
+        unreachable!("is_io_error only holds for the Io kind");
+    };
+    EngineError::Io(io_error)
+}
+
+// Nanoseconds since the previous lap of a running clock, restarting it. Zero if timing is off.
+fn lap(clock: &mut Option<Instant>) -> u64 {
+    clock.as_mut().map_or(0, |started| {
+        let now = Instant::now();
+        let elapsed = now.duration_since(*started);
+        *started = now;
+        u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX)
+    })
+}
+
+// Hashes one transaction together with the checksum so far, so the result depends on order.
+fn chain_checksum(checksum: u64, transaction: &InputTransaction) -> u64 {
+    let TransactionIds { client, tx } = transaction.ids();
+    let amount = transaction.amount().unwrap_or_default().normalize().serialize();
+    [
+        &checksum.to_le_bytes()[..],
+        transaction.kind().as_bytes(),
+        &client.to_le_bytes(),
+        &tx.to_le_bytes(),
+        &amount,
+    ]
+    .iter()
+    .flat_map(|bytes| bytes.iter())
+    .fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// Whether the stored and computed totals match. `Decimal` rounds results beyond its 28 or so
+// significant digits, and the two totals round at different steps, so once an account's balances
+// have been that large they may differ in the last digits at that magnitude.
+#[cfg(debug_assertions)]
+fn totals_agree(stored: Decimal, computed: Decimal, peak: Decimal) -> bool {
+    let tolerance = peak.checked_mul(Decimal::new(1, 20)).unwrap_or(Decimal::MAX);
+    stored
+        .checked_sub(computed)
+        .is_some_and(|difference| difference.abs() <= tolerance)
+}
+
+// An error naming the out-of-range id when a row failed to deserialize because its `client` or
+// `tx` is a whole number too large for the id type, which csv only reports as a generic parse
+// failure.
+fn id_range_error(record: &csv::StringRecord, headers: &csv::StringRecord) -> Option<EngineError> {
+    let field = |column: &str| {
+        let index = headers.iter().position(|header| header.trim() == column)?;
+        record.get(index)?.trim().parse::<u128>().ok()
+    };
+    let line = record.position().map_or(0, csv::Position::line);
+    let out_of_range = |name: &str, value: Option<u128>, max: u128, type_name: &str| {
+        value.filter(|value| *value > max).map(|value| {
+            EngineError::InputValidation(format!("{name} id {value} exceeds {type_name} range on line {line}"))
+        })
+    };
+    out_of_range(
+        "client",
+        field("client"),
+        ClientId::MAX.into(),
+        core::any::type_name::<ClientId>(),
+    )
+    .or_else(|| {
+        out_of_range(
+            "tx",
+            field("tx"),
+            TransactionId::MAX.into(),
+            core::any::type_name::<TransactionId>(),
+        )
+    })
+}
+
+// Checks a header row has each canonical column exactly once, plus optional columns the engine
+// knows about. An empty header row is an empty input and passes.
+fn check_headers(headers: &csv::StringRecord, amount_columns: &[&str]) -> Result<(), String> {
+    if headers.is_empty() {
+        return Ok(());
+    }
+    let mut required = REQUIRED_COLUMNS.iter().chain(amount_columns);
+    let mut seen = HashSet::new();
+    for header in headers {
+        if !seen.insert(header) {
+            return Err(format!("duplicate column {header:?}"));
+        }
+        if !required.clone().any(|column| *column == header) && !OPTIONAL_COLUMNS.contains(&header) {
+            return Err(format!("unexpected column {header:?}"));
+        }
+    }
+    if let Some(column) = required.find(|column| !seen.contains(*column)) {
+        return Err(format!("missing column {column:?}"));
+    }
+    Ok(())
+}
+
+// Rewrites a scientific notation amount such as `1.5e2` as a plain decimal string. Returns `None`
+// for anything that isn't scientific notation or has an exponent no `Decimal` could hold.
+fn expand_scientific(amount: &str) -> Option<String> {
+    let (_, exponent) = amount.split_once(['e', 'E'])?;
+    if exponent.parse::<i32>().ok()?.unsigned_abs() > MAX_SCIENTIFIC_EXPONENT {
+        return None;
+    }
+    Decimal::from_scientific(amount).ok().map(|amount| amount.to_string())
+}
+
+fn format_decimal(value: Decimal, scale: u32, zero_display: ZeroDisplay) -> String {
+    let mut value = value.round_dp(scale);
+    if value.is_zero() {
+        // Rounding a tiny negative amount, or subtracting equal amounts, can leave a negative zero.
+        value = Decimal::ZERO;
+    }
+    let fixed = match zero_display {
+        ZeroDisplay::Normalized => false,
+        ZeroDisplay::FixedZero => value.is_zero(),
+        ZeroDisplay::FixedScale => true,
+    };
+    if fixed {
+        value.rescale(scale);
+        value.to_string()
+    } else {
+        value.normalize().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn deposit_and_withdraw() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("1.24")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("0.5")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("0.74").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn dispute_and_resolve_cycle() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("1.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("resolve", 1, 1, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("3.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn chargeback_locks_account() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("3.5")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("deposit", 1, 2, Some("5.0")));
+        engine.process_record(raw("chargeback", 1, 1, None));
+        engine.process_record(raw("deposit", 1, 3, Some("1.0")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn withdrawal_before_any_deposit_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("withdrawal", 1, 1, Some("1.0")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::ZERO);
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.transaction_count(), 0);
+    }
+
+    #[test]
+    fn skips_insufficient_withdrawal() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("1.0")));
+        engine.process_record(raw("deposit", 1, 3, Some("1.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("2.01")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+    }
+
+    #[test]
+    fn withdrawal_does_not_use_held_funds() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("deposit", 1, 5, Some("2.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("withdrawal", 1, 2, Some("3.0")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn disputing_already_disputed_transaction_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("dispute", 1, 1, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::ZERO);
+        assert_eq!(account.held, Decimal::from_str("2.0").unwrap());
+        let Transaction::Deposit(deposit) = account.transactions.get(&1).unwrap() else {
+            panic!("expected deposit transaction");
+        };
+        assert!(matches!(deposit.state, TransactionState::Disputed));
+    }
+
+    #[test]
+    fn resolve_not_in_dispute_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("resolve", 1, 1, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(matches!(account.deposit_state(1), Some(TransactionState::Normal)));
+    }
+
+    #[test]
+    fn chargeback_not_in_dispute_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("chargeback", 1, 1, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+        assert!(matches!(account.deposit_state(1), Some(TransactionState::Normal)));
+    }
+
+    #[test]
+    fn dispute_or_resolution_on_withdrawal_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("1.0")));
+        engine.process_record(raw("dispute", 1, 2, None));
+        engine.process_record(raw("resolve", 1, 2, None));
+        engine.process_record(raw("chargeback", 1, 2, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("1.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+        let Transaction::Withdrawal(withdrawal) = account.transactions.get(&2).unwrap() else {
+            panic!("expected withdrawal transaction");
+        };
+        assert_eq!(withdrawal.amount, Decimal::from_str("1.0").unwrap());
+    }
+
+    #[test]
+    fn dispute_or_resolution_on_missing_transaction_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("dispute", 1, 98, None));
+        engine.process_record(raw("resolve", 1, 99, None));
+        engine.process_record(raw("chargeback", 1, 99, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+        assert!(matches!(account.deposit_state(1), Some(TransactionState::Normal)));
+    }
+
+    #[test]
+    fn all_transaction_types_are_ignored_on_locked_account() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("3.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+
+        engine.process_record(raw("deposit", 1, 2, Some("1.0")));
+        engine.process_record(raw("withdrawal", 1, 3, Some("1.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("resolve", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::ZERO);
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(account.locked);
+        assert_eq!(account.transaction_count(), 1);
+        let Transaction::Deposit(deposit) = account.transactions.get(&1).unwrap() else {
+            panic!("expected deposit transaction");
+        };
+        assert!(matches!(deposit.state, TransactionState::ChargedBack));
+    }
+
+    #[test]
+    fn duplicate_transactions_ids_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("1.24")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("0.5")));
+        engine.process_record(raw("deposit", 1, 2, Some("5")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("0.74").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn max_held_transaction_returns_largest_dispute() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("7.5")));
+        engine.process_record(raw("deposit", 1, 3, Some("10.0")));
+        assert_eq!(engine.max_held_transaction(1), None);
+
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("dispute", 1, 2, None));
+
+        assert_eq!(
+            engine.max_held_transaction(1),
+            Some((2, Decimal::from_str("7.5").unwrap()))
+        );
+        assert_eq!(engine.max_held_transaction(2), None);
+    }
+
+    #[test]
+    fn open_disputes_lists_unresolved_disputes() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("3.5")));
+        engine.process_record(raw("deposit", 2, 3, Some("4.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("resolve", 1, 1, None));
+        engine.process_record(raw("dispute", 1, 2, None));
+
+        let disputes = engine.open_disputes();
+        assert_eq!(disputes, [(1, 2, Decimal::from_str("3.5").unwrap())]);
+        assert_eq!(disputes[0].2, engine.accounts.get(&1).unwrap().held);
+    }
+
+    #[test]
+    fn lifetime_totals_ignore_dispute_lifecycle() {
+        let dec = |value| Decimal::from_str(value).unwrap();
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("10.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("4.0")));
+        engine.process_record(raw("deposit", 1, 3, Some("2.5")));
+        engine.process_record(raw("dispute", 1, 3, None));
+        engine.process_record(raw("chargeback", 1, 3, None));
+
+        assert_eq!(
+            engine.account(1),
+            Some(AccountSnapshot {
+                available: dec("6.0"),
+                held: Decimal::ZERO,
+                total: dec("6.0"),
+                locked: true,
+                lifetime_deposited: dec("12.5"),
+                lifetime_withdrawn: dec("4.0"),
+                opening_balance: None,
+            })
+        );
+        assert_eq!(engine.account(2), None);
+    }
+
+    #[test]
+    fn account_map_is_independent_of_later_mutations() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        let snapshot = engine.to_account_map();
+
+        engine.process_record(raw("deposit", 1, 2, Some("3.0")));
+        engine.process_record(raw("deposit", 2, 3, Some("1.0")));
+
+        let reader = std::thread::spawn(move || (snapshot.len(), snapshot[&1].available));
+        assert_eq!(reader.join().unwrap(), (1, Decimal::from_str("2.0").unwrap()));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from_str("5.0").unwrap());
+    }
+
+    #[test]
+    fn descriptions_round_trip_into_history() {
+        let input = "type,client,tx,amount,description\n\
+                     deposit,1,2,5.0,payroll march\n\
+                     withdrawal,1,1,1.0,\n\
+                     dispute,1,2,,customer called\n";
+        let mut engine = Engine::default();
+        engine.apply_transactions(input.as_bytes()).unwrap();
+
+        let dec = |value| Decimal::from_str(value).unwrap();
+        assert_eq!(
+            engine.transaction_history(1),
+            Some(vec![
+                HistoryEntry {
+                    tx: 1,
+                    kind: HistoryKind::Withdrawal,
+                    amount: dec("1.0"),
+                    description: None,
+                    state: None,
+                },
+                HistoryEntry {
+                    tx: 2,
+                    kind: HistoryKind::Deposit,
+                    amount: dec("5.0"),
+                    description: Some("payroll march".to_string()),
+                    state: Some(PublicTxState::Disputed),
+                },
+            ])
+        );
+        assert_eq!(engine.transaction_history(2), None);
+    }
+
+    #[test]
+    fn history_of_locked_account_shows_charged_back_deposit() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("3.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+        assert!(engine.account(1).unwrap().locked);
+
+        let states: Vec<_> = engine
+            .transaction_history(1)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.tx, entry.state))
+            .collect();
+        assert_eq!(
+            states,
+            [(1, Some(PublicTxState::ChargedBack)), (2, Some(PublicTxState::Normal))]
+        );
+    }
+
+    #[test]
+    fn transaction_state_follows_dispute_lifecycle() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("1.0")));
+        assert_eq!(engine.transaction_state(1, 1), Some(PublicTxState::Normal));
+
+        engine.process_record(raw("dispute", 1, 1, None));
+        assert_eq!(engine.transaction_state(1, 1), Some(PublicTxState::Disputed));
+        assert_eq!(engine.transaction_state(1, 2), None);
+        assert_eq!(engine.transaction_state(1, 3), None);
+        assert_eq!(engine.transaction_state(2, 1), None);
+    }
+
+    #[test]
+    fn client_stats_aggregates_mixed_activity() {
+        let dec = |value| Decimal::from_str(value).unwrap();
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("3.5")));
+        engine.process_record(raw("deposit", 1, 3, Some("1.0")));
+        engine.process_record(raw("withdrawal", 1, 4, Some("1.5")));
+        engine.process_record(raw("withdrawal", 1, 5, Some("100.0")));
+        engine.process_record(raw("adjustment", 1, 6, Some("-0.5")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("resolve", 1, 1, None));
+        engine.process_record(raw("dispute", 1, 2, None));
+
+        assert_eq!(
+            engine.client_stats(1),
+            Some(ClientStats {
+                deposit_count: 3,
+                withdrawal_count: 1,
+                dispute_count: 2,
+                total_deposited: dec("6.5"),
+                total_withdrawn: dec("1.5"),
+            })
+        );
+        assert_eq!(engine.client_stats(2), None);
+    }
+
+    #[test]
+    fn held_ratio_divides_held_by_total() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("20")));
+        engine.process_record(raw("deposit", 1, 2, Some("1")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("deposit", 2, 3, Some("1")));
+        engine.process_record(raw("withdrawal", 2, 4, Some("1")));
+
+        assert_eq!(
+            engine.held_ratio(1).map(|ratio| ratio.round_dp(3)),
+            Some(Decimal::from_str("0.952").unwrap())
+        );
+        assert_eq!(engine.held_ratio(2), None);
+        assert_eq!(engine.held_ratio(3), None);
+    }
+
+    #[test]
+    fn positive_and_negative_adjustments_change_available() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("adjustment", 1, 2, Some("1.5")));
+        engine.process_record(raw("adjustment", 1, 3, Some("-4.0")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.5").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(matches!(account.transactions.get(&3), Some(Transaction::Adjustment(_))));
+    }
+
+    #[test]
+    fn disputing_adjustment_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("adjustment", 1, 1, Some("3.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("3.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn adjustment_on_locked_account_is_ignored() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("3.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+        engine.process_record(raw("adjustment", 1, 2, Some("3.0")));
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::ZERO);
+        assert!(!account.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn cloned_engine_is_independent() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+
+        let mut branch = engine.clone();
+        branch.process_record(raw("deposit", 1, 2, Some("3.0")));
+        branch.process_record(raw("dispute", 1, 1, None));
+
+        let original = engine.accounts.get(&1).unwrap();
+        assert_eq!(original.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(original.held, Decimal::ZERO);
+        assert_eq!(original.transaction_count(), 1);
+        assert!(!engine.transaction_ids_processed.contains(&2));
+
+        let branched = branch.accounts.get(&1).unwrap();
+        assert_eq!(branched.available, Decimal::from_str("3.0").unwrap());
+        assert_eq!(branched.held, Decimal::from_str("2.0").unwrap());
+    }
+
+    #[test]
+    fn finalize_drops_dedup_set_and_keeps_balances() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("0.5")));
+
+        let finalized = engine.finalize();
+        assert!(finalized.transaction_ids_processed.is_empty());
+
+        let mut output = Vec::new();
+        finalized.write_accounts(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output, "client,available,held,total,locked\n1,1.5,0,1.5,false\n");
+    }
+
+    #[test]
+    fn reconciled_totals_add_up_after_rounding() {
+        fn written(engine: &Engine) -> String {
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("0.00006")));
+        engine.process_record(raw("deposit", 1, 2, Some("0.00006")));
+        engine.process_record(raw("dispute", 1, 2, None));
+
+        assert_eq!(
+            written(&engine),
+            "client,available,held,total,locked\n1,0.0001,0.0001,0.0001,false\n"
+        );
+        let engine = engine.with_reconciled_totals(true);
+        assert_eq!(
+            written(&engine),
+            "client,available,held,total,locked\n1,0.0001,0.0001,0.0002,false\n"
+        );
+    }
+
+    #[test]
+    fn outcomes_report_each_skip_path() {
+        let mut engine = Engine::default();
+        assert_eq!(engine.apply(raw("dispute", 1, 1, None)), TransactionOutcome::Ignored);
+        assert_eq!(
+            engine.apply(raw("deposit", 1, 1, Some("2.0"))),
+            TransactionOutcome::Applied
+        );
+        assert_eq!(
+            engine.apply(raw("deposit", 1, 1, Some("2.0"))),
+            TransactionOutcome::SkippedDuplicate
+        );
+        assert_eq!(
+            engine.apply(raw("withdrawal", 1, 2, Some("5.0"))),
+            TransactionOutcome::SkippedInsufficientFunds
+        );
+        assert_eq!(engine.apply(raw("resolve", 1, 1, None)), TransactionOutcome::Ignored);
+        assert_eq!(engine.apply(raw("dispute", 1, 1, None)), TransactionOutcome::Applied);
+        assert_eq!(engine.apply(raw("chargeback", 1, 1, None)), TransactionOutcome::Applied);
+        assert_eq!(
+            engine.apply(raw("deposit", 1, 3, Some("1.0"))),
+            TransactionOutcome::SkippedLocked
+        );
+        assert_eq!(
+            engine.apply(raw("dispute", 1, 1, None)),
+            TransactionOutcome::SkippedLocked
+        );
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn applies_transactions_from_socket() {
+        use std::{
+            io::Write,
+            net::{TcpListener, TcpStream},
+            thread,
+            time::Duration,
+        };
+
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let server_addr = addr.clone();
+        let server = thread::spawn(move || {
+            let mut engine = Engine::default();
+            engine.apply_transactions_from_socket(&server_addr).unwrap();
+            engine
+        });
+
+        let mut stream = loop {
+            match TcpStream::connect(&addr) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        stream
+            .write_all(b"type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,0.5\n")
+            .unwrap();
+        drop(stream);
+
+        let engine = server.join().unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("1.5").unwrap());
+    }
+
+    #[test]
+    fn resolve_after_resolve_depends_on_strict_mode() {
+        for strict in [false, true] {
+            let mut engine = Engine::default().with_strict_state_transitions(strict);
+            engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+            engine.process_record(raw("dispute", 1, 1, None));
+            assert_eq!(engine.apply(raw("resolve", 1, 1, None)), TransactionOutcome::Applied);
+
+            let expected = if strict {
+                TransactionOutcome::InvalidTransition
+            } else {
+                TransactionOutcome::Ignored
+            };
+            assert_eq!(engine.apply(raw("resolve", 1, 1, None)), expected);
+            assert_eq!(engine.summary().invalid_transitions, usize::from(strict));
+            let account = engine.accounts.get(&1).unwrap();
+            assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+            assert_eq!(account.held, Decimal::ZERO);
+        }
+    }
+
+    #[test]
+    fn resolve_after_chargeback_depends_on_strict_mode() {
+        for strict in [false, true] {
+            let mut engine = Engine::default().with_strict_state_transitions(strict);
+            engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+            engine.process_record(raw("dispute", 1, 1, None));
+            engine.process_record(raw("chargeback", 1, 1, None));
+
+            let expected = if strict {
+                TransactionOutcome::InvalidTransition
+            } else {
+                TransactionOutcome::SkippedLocked
+            };
+            assert_eq!(engine.apply(raw("resolve", 1, 1, None)), expected);
+            assert_eq!(engine.summary().invalid_transitions, usize::from(strict));
+            let account = engine.accounts.get(&1).unwrap();
+            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.held, Decimal::ZERO);
+            assert!(account.locked);
+        }
+    }
+
+    #[test]
+    fn reserved_client_transactions_are_skipped() {
+        let mut engine = Engine::default().with_reserved_clients(0..=0);
+        assert_eq!(
+            engine.try_deposit(0, 1, Decimal::ONE),
+            Err(TransactionError::ReservedClient)
+        );
+        assert_eq!(engine.try_deposit(1, 2, Decimal::ONE), Ok(()));
+
+        assert_eq!(engine.summary().skipped_reserved_client, 1);
+        assert!(!engine.accounts.contains_key(&0));
+    }
+
+    #[test]
+    fn withdrawal_must_leave_minimum_balance() {
+        let mut engine = Engine::default().with_minimum_balance(Decimal::ONE);
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        assert_eq!(
+            engine.try_withdraw(1, 2, Decimal::from_str("1.5").unwrap()),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.try_withdraw(1, 3, Decimal::ONE), Ok(()));
+        assert_eq!(engine.accounts.get(&1).unwrap().available, Decimal::ONE);
+    }
+
+    #[test]
+    fn withdrawal_blocked_during_open_dispute() {
+        let mut engine = Engine::default().with_block_withdrawals_during_dispute(true);
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("5.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        assert_eq!(
+            engine.try_withdraw(1, 3, Decimal::ONE),
+            Err(TransactionError::OpenDispute)
+        );
+        assert_eq!(engine.summary().skipped_open_dispute, 1);
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available,
+            Decimal::from_str("5.0").unwrap()
+        );
+
+        engine.process_record(raw("resolve", 1, 1, None));
+        assert_eq!(engine.try_withdraw(1, 4, Decimal::ONE), Ok(()));
+    }
+
+    #[test]
+    fn dispute_breaching_balance_floor_is_skipped() {
+        let mut engine = Engine::default().with_balance_bounds(Some(Decimal::ZERO), None);
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("3.0")));
+        assert_eq!(
+            engine.apply(raw("dispute", 1, 1, None)),
+            TransactionOutcome::SkippedBalanceBounds
+        );
+        assert_eq!(engine.summary().skipped_balance_bounds, 1);
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(matches!(account.deposit_state(1), Some(TransactionState::Normal)));
+    }
+
+    #[test]
+    fn transaction_ledger_lists_disputed_deposit() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 2, 3, Some("1.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("5.0")));
+        engine.process_record(raw("withdrawal", 1, 1, Some("1.5")));
+        engine.process_record(raw("dispute", 1, 2, None));
+
+        let mut output = Vec::new();
+        engine.write_transactions_csv(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,type,amount,state\n\
+             1,1,withdrawal,1.5,\n\
+             1,2,deposit,5,disputed\n\
+             2,3,deposit,1,normal\n"
+        );
+    }
+
+    #[test]
+    fn dispute_after_partial_withdrawal_holds_by_basis() {
+        let dec = |value| Decimal::from_str(value).unwrap();
+        let balances = |basis| {
+            let mut engine = Engine::default().with_dispute_hold_basis(basis);
+            engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+            engine.process_record(raw("withdrawal", 1, 2, Some("3.0")));
+            engine.process_record(raw("dispute", 1, 1, None));
+            let disputed = engine.account(1).unwrap();
+            engine.process_record(raw("chargeback", 1, 1, None));
+            let charged_back = engine.account(1).unwrap();
+            (disputed, charged_back)
+        };
+
+        let (disputed, charged_back) = balances(DisputeHoldBasis::OriginalAmount);
+        assert_eq!((disputed.available, disputed.held), (dec("-3.0"), dec("5.0")));
+        assert_eq!(
+            (charged_back.available, charged_back.held),
+            (dec("-3.0"), Decimal::ZERO)
+        );
+
+        let (disputed, charged_back) = balances(DisputeHoldBasis::RemainingAvailable);
+        assert_eq!((disputed.available, disputed.held), (Decimal::ZERO, dec("2.0")));
+        assert_eq!(
+            (charged_back.available, charged_back.held),
+            (Decimal::ZERO, Decimal::ZERO)
+        );
+        assert!(charged_back.locked);
+    }
+
+    #[test]
+    fn resolve_releases_only_what_the_dispute_held() {
+        let mut engine = Engine::default().with_dispute_hold_basis(DisputeHoldBasis::RemainingAvailable);
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("3.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("resolve", 1, 1, None));
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("2.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn deposit_breaching_balance_ceiling_is_skipped() {
+        let mut engine = Engine::default().with_balance_bounds(None, Some(Decimal::from(10)));
+        engine.process_record(raw("deposit", 1, 1, Some("8.0")));
+        assert_eq!(
+            engine.apply(raw("deposit", 1, 2, Some("3.0"))),
+            TransactionOutcome::SkippedBalanceBounds
+        );
+        assert_eq!(
+            engine.apply(raw("deposit", 1, 3, Some("2.0"))),
+            TransactionOutcome::Applied
+        );
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from(10));
+    }
+
+    #[test]
+    fn try_deposit_and_withdraw_report_errors() {
+        let dec = |value| Decimal::from_str(value).unwrap();
+        let mut engine = Engine::default();
+
+        assert_eq!(engine.try_deposit(1, 1, dec("0")), Err(TransactionError::InvalidAmount));
+        assert_eq!(
+            engine.try_withdraw(1, 2, dec("-1.0")),
+            Err(TransactionError::InvalidAmount)
+        );
+        assert_eq!(engine.try_deposit(1, 3, dec("5.0")), Ok(()));
+        assert_eq!(engine.try_deposit(1, 3, dec("5.0")), Err(TransactionError::Duplicate));
+        assert_eq!(
+            engine.try_withdraw(1, 4, dec("6.0")),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.try_withdraw(1, 5, dec("1.0")), Ok(()));
+
+        engine.process_record(raw("dispute", 1, 3, None));
+        engine.process_record(raw("chargeback", 1, 3, None));
+        assert_eq!(engine.try_deposit(1, 6, dec("1.0")), Err(TransactionError::Locked));
+        assert_eq!(engine.try_withdraw(1, 7, dec("1.0")), Err(TransactionError::Locked));
+        assert_eq!(engine.summary().skipped_invalid_amount, 2);
+    }
+
+    #[test]
+    fn audit_writer_streams_applied_events() {
+        #[derive(Clone, Default)]
+        struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = SharedBuffer::default();
+        let mut engine = Engine::default().with_audit_writer(buffer.clone());
+        engine.process_record(raw("deposit", 1, 1, Some("2.5")));
+        engine.process_record(raw("resolve", 1, 1, None));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            serde_json::json!({"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"})
+        );
+        assert_eq!(events[1], serde_json::json!({"type": "dispute", "client": 1, "tx": 1}));
+        assert_eq!(
+            events[2],
+            serde_json::json!({"type": "chargeback", "client": 1, "tx": 1})
+        );
+        assert_eq!(engine.summary().audit_write_errors, 0);
+    }
+
+    #[cfg(feature = "btree")]
+    #[test]
+    fn btree_accounts_are_written_in_client_order() {
+        let mut engine = Engine::default();
+        for (tx, client) in [3, 1, 2, 10].into_iter().enumerate() {
+            engine.process_record(raw("deposit", client, u32::try_from(tx).unwrap(), Some("1.0")));
+        }
+        let mut output = Vec::new();
+        engine.write_accounts(&mut output).unwrap();
+        let clients: Vec<_> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_string())
+            .collect();
+        assert_eq!(clients, ["1", "2", "3", "10"]);
+    }
+
+    #[test]
+    fn output_order_first_seen_differs_from_sorted() {
+        fn written_clients(engine: &Engine) -> Vec<String> {
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output).unwrap();
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').next().unwrap().to_string())
+                .collect()
+        }
+
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 3, 1, Some("1.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("1.0")));
+        engine.process_record(raw("deposit", 2, 3, Some("1.0")));
+        engine.process_record(raw("deposit", 3, 4, Some("1.0")));
+
+        let engine = engine.with_output_order(OutputOrder::FirstSeen);
+        assert_eq!(written_clients(&engine), ["3", "1", "2"]);
+        let engine = engine.with_output_order(OutputOrder::Sorted);
+        assert_eq!(written_clients(&engine), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn repeated_dispute_is_counted_once() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("4.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("dispute", 1, 1, None));
+
+        assert_eq!(engine.summary().duplicate_dispute_attempts, 1);
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::ZERO);
+        assert_eq!(account.held, Decimal::from_str("4.0").unwrap());
+    }
+
+    #[test]
+    fn dispute_after_chargeback_is_counted() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("4.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("1.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+        assert_eq!(engine.summary().post_chargeback_attempts, 0);
+
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("resolve", 1, 1, None));
+        // Tx 2 was never charged back, it's only blocked by the lock.
+        engine.process_record(raw("dispute", 1, 2, None));
+
+        assert_eq!(engine.summary().post_chargeback_attempts, 2);
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("1.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(account.locked);
+    }
+
+    // B-tree maps have no capacity to check.
+    #[cfg(not(feature = "btree"))]
+    #[test]
+    fn with_capacity_avoids_reallocating_within_capacity() {
+        let mut presized = Engine::with_capacity(100, 10_000);
+        let account_capacity = presized.accounts.capacity();
+        let transaction_capacity = presized.transaction_ids_processed.capacity();
+        assert!(account_capacity >= 100);
+        assert!(transaction_capacity >= 10_000);
+
+        let mut unsized_engine = Engine::default();
+        let mut unsized_growths = 0;
+        for tx in 0..10_000 {
+            let client = u16::try_from(tx % 100).unwrap();
+            let deposit = InputTransaction::Deposit(TransactionIds { client, tx }, Decimal::ONE);
+            presized.process_record(deposit);
+
+            let before = unsized_engine.transaction_ids_processed.capacity();
+            unsized_engine.process_record(deposit);
+            if unsized_engine.transaction_ids_processed.capacity() != before {
+                unsized_growths += 1;
+            }
+        }
+
+        assert_eq!(presized.accounts.capacity(), account_capacity);
+        assert_eq!(presized.transaction_ids_processed.capacity(), transaction_capacity);
+        assert!(unsized_growths > 0);
+    }
+
+    #[test]
+    fn dispute_on_unknown_client_is_counted_in_strict_mode() {
+        for strict in [false, true] {
+            let mut engine = Engine::default().with_strict_state_transitions(strict);
+            engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+            engine.process_record(raw("dispute", 99, 1, None));
+            // Known client, unknown tx: still just ignored.
+            engine.process_record(raw("dispute", 1, 5, None));
+
+            assert_eq!(engine.summary().dispute_unknown_client, usize::from(strict));
+            assert_eq!(engine.summary().ignored, 2 - usize::from(strict));
+            assert!(!engine.accounts.contains_key(&99));
+            assert_eq!(engine.accounts.get(&1).unwrap().held, Decimal::ZERO);
+        }
+    }
+
+    #[test]
+    fn opening_balance_funds_later_withdrawals() {
+        let mut engine = Engine::default();
+        engine.set_opening_balance(1, Decimal::from(10)).unwrap();
+        engine.set_opening_balance(1, Decimal::from(8)).unwrap();
+        assert_eq!(
+            engine.process_record(raw("withdrawal", 1, 1, Some("3.0"))),
+            TransactionOutcome::Applied
+        );
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available, Decimal::from(5));
+        assert_eq!(account.total, Decimal::from(5));
+        assert_eq!(account.opening_balance, Some(Decimal::from(8)));
+        assert_eq!(
+            engine.set_opening_balance(1, Decimal::from(10)),
+            Err(TransactionError::HasTransactions)
+        );
+        assert_eq!(
+            engine.process_record(raw("dispute", 1, 0, None)),
+            TransactionOutcome::Ignored
+        );
+    }
+
+    #[test]
+    fn stored_and_computed_totals_agree() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.5\ndeposit,1,2,2.25\nwithdrawal,1,3,1.0\n\
+                     adjustment,1,4,-0.5\ndispute,1,1,\nresolve,1,1,\ndeposit,2,5,3.0\n\
+                     dispute,2,5,\nchargeback,2,5,\ndeposit,3,6,9.0\ndispute,3,6,\n";
+        let output = |source| {
+            let mut engine = Engine::default()
+                .with_output_order(OutputOrder::Sorted)
+                .with_total_source(source);
+            engine.apply_transactions(input.as_bytes()).unwrap();
+            engine.reverse_chargeback(2, 5).unwrap();
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(output(TotalSource::Stored), output(TotalSource::Computed));
+    }
+
+    #[test]
+    fn can_withdraw_checks_lock_and_funds() {
+        let mut engine = Engine::default().with_minimum_balance(Decimal::ONE);
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("deposit", 2, 2, Some("5.0")));
+        engine.process_record(raw("dispute", 2, 2, None));
+        engine.process_record(raw("chargeback", 2, 2, None));
+
+        assert!(engine.can_withdraw(1, Decimal::from(4)));
+        assert!(!engine.can_withdraw(1, Decimal::new(41, 1)));
+        assert!(!engine.can_withdraw(1, Decimal::ZERO));
+        assert!(!engine.can_withdraw(2, Decimal::ZERO));
+        assert!(!engine.can_withdraw(2, Decimal::ONE));
+        assert!(!engine.can_withdraw(3, Decimal::ONE));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(5));
+    }
+
+    #[test]
+    fn out_of_range_ids_are_reported_with_the_line() {
+        let mut engine = Engine::default();
+        let errors = engine
+            .apply_transactions_collecting(
+                "type,client,tx,amount\ndeposit,70000,1,1.0\ndeposit,1,4294967296,1.0\n".as_bytes(),
+            )
+            .unwrap();
+        let reasons: Vec<_> = errors.iter().map(|error| error.reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "input transaction validation error: client id 70000 exceeds u16 range on line 2",
+                "input transaction validation error: tx id 4294967296 exceeds u32 range on line 3",
+            ]
+        );
+        assert_eq!(engine.summary().malformed_rows, 2);
+    }
+
+    #[test]
+    fn skipped_dispute_succeeds_on_retry() {
+        let mut engine = Engine::default();
+        let skipped = engine
+            .apply_transactions_collecting(
+                "type,client,tx,amount\ndispute,1,1,\ndeposit,1,1,5.0\ndeposit,1,x,1.0\n".as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(engine.account(1).unwrap().held, Decimal::ZERO);
+
+        let retried = engine.retry_skipped(&skipped);
+        assert_eq!(retried.applied, 1);
+        assert_eq!(retried.malformed_rows, 1);
+        assert_eq!(engine.account(1).unwrap().held, Decimal::from(5));
+        assert_eq!(engine.transaction_state(1, 1), Some(PublicTxState::Disputed));
+    }
+
+    #[test]
+    fn withdrawals_past_velocity_limit_are_skipped() {
+        let input = "type,client,tx,amount\ndeposit,1,1,500.0\ndeposit,2,2,500.0\n\
+                     withdrawal,1,3,10.0\nwithdrawal,1,4,20.0\nwithdrawal,1,5,1.0\n\
+                     withdrawal,2,6,60.0\nwithdrawal,2,7,50.0\nwithdrawal,2,8,40.0\n";
+        let mut engine = Engine::default().with_withdrawal_velocity_limit(2, Decimal::from(100));
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(470));
+        assert_eq!(engine.account(2).unwrap().available, Decimal::from(400));
+        assert_eq!(engine.summary().skipped_velocity_limit, 2);
+
+        // The counters start over with the next batch.
+        engine
+            .apply_transactions("type,client,tx,amount\nwithdrawal,1,9,1.0\n".as_bytes())
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(469));
+    }
+
+    #[test]
+    fn velocity_limit_can_freeze_the_account() {
+        let mut engine = Engine::default()
+            .with_withdrawal_velocity_limit(1, Decimal::MAX)
+            .with_freeze_on_velocity_limit(true);
+        let outcomes = engine.apply_batch([
+            raw("deposit", 1, 1, Some("5.0")),
+            raw("withdrawal", 1, 2, Some("1.0")),
+            raw("withdrawal", 1, 3, Some("1.0")),
+            raw("deposit", 1, 4, Some("1.0")),
+        ]);
+        assert_eq!(
+            outcomes,
+            [
+                TransactionOutcome::Applied,
+                TransactionOutcome::Applied,
+                TransactionOutcome::SkippedVelocityLimit,
+                TransactionOutcome::SkippedLocked,
+            ]
+        );
+        assert_eq!(engine.lock_reason(1), Some(LockReason::VelocityLimit));
+    }
+
+    #[test]
+    fn fixed_width_matches_csv_equivalent() {
+        let layout = FixedWidthLayout {
+            tx_type: 0..10,
+            client: 10..15,
+            tx: 15..22,
+            amount: 22..34,
+        };
+        let fixed_width = "\
+deposit       1      1      10.5
+deposit       2      2       3.0
+withdrawal    1      3       2.25
+
+dispute       2      2
+deposit       x      4       1.0
+";
+        let csv = "type,client,tx,amount\ndeposit,1,1,10.5\ndeposit,2,2,3.0\nwithdrawal,1,3,2.25\ndispute,2,2,\n";
+        let output = |engine: &Engine| {
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let mut from_fixed_width = Engine::default().with_output_order(OutputOrder::Sorted);
+        from_fixed_width
+            .apply_transactions_fixed_width(fixed_width.as_bytes(), &layout)
+            .unwrap();
+        let mut from_csv = Engine::default().with_output_order(OutputOrder::Sorted);
+        from_csv.apply_transactions(csv.as_bytes()).unwrap();
+        assert_eq!(output(&from_fixed_width), output(&from_csv));
+        assert_eq!(from_fixed_width.summary().malformed_rows, 1);
+        assert_eq!(from_fixed_width.account(2).unwrap().held, Decimal::from(3));
+    }
+
+    #[test]
+    fn unclosed_disputes_fail_strict_and_count_lenient() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\ndeposit,2,3,1.0\n\
+                     dispute,1,1,\ndispute,1,2,\nresolve,1,2,\n";
+        let mut strict = Engine::default()
+            .with_strict_state_transitions(true)
+            .with_require_disputes_closed(true);
+        assert!(matches!(
+            strict.apply_transactions(input.as_bytes()),
+            Err(EngineError::OpenDisputes(open)) if open == [(1, 1)]
+        ));
+
+        let mut lenient = Engine::default().with_require_disputes_closed(true);
+        lenient.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(lenient.summary().open_disputes_at_end, 1);
+
+        let mut unchecked = Engine::default();
+        unchecked.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(unchecked.summary().open_disputes_at_end, 0);
+    }
+
+    #[test]
+    fn sharded_apply_enforces_client_limit_across_shards() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,1.0\ndeposit,3,3,1.0\ndeposit,1,4,1.0\n";
+        let mut engine = Engine::default().with_max_clients(2);
+        engine
+            .apply_transactions_sharded(input.as_bytes(), NonZeroUsize::new(3).unwrap(), 1)
+            .unwrap();
+        assert_eq!(engine.account_count(), 2);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(2));
+        assert_eq!(engine.summary().skipped_client_limit, 1);
+    }
+
+    #[test]
+    fn sharded_apply_matches_sequential() {
+        use std::fmt::Write as _;
+
+        // Ten rows per client visit: six deposits, two withdrawals, a dispute of the last deposit
+        // and then its resolve or, for one visit in five, its chargeback.
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=100_000_u32 {
+            let client = tx / 10 % 1_000;
+            let row = match tx % 10 {
+                0..=5 => format!("deposit,{client},{tx},{}.5", tx % 97),
+                6 | 7 => format!("withdrawal,{client},{tx},{}.25", tx % 89),
+                8 => format!("dispute,{client},{},", tx - 3),
+                _ if tx / 10 % 5 == 0 => format!("chargeback,{client},{},", tx - 4),
+                _ => format!("resolve,{client},{},", tx - 4),
+            };
+            writeln!(input, "{row}").unwrap();
+        }
+        // A deposit to another client reusing tx 1, which only a check across shards catches.
+        input.push_str("deposit,1,x,1.0\ndeposit,2,1,7.0\n");
+        let run = |sharded: bool| {
+            let mut engine = Engine::default().with_output_order(OutputOrder::Sorted);
+            engine.process_record(raw("deposit", 7, 200_000, Some("3.0")));
+            if sharded {
+                engine
+                    .apply_transactions_sharded(input.as_bytes(), NonZeroUsize::new(4).unwrap(), 1)
+                    .unwrap();
+            } else {
+                engine.apply_transactions(input.as_bytes()).unwrap();
+            }
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output).unwrap();
+            (String::from_utf8(output).unwrap(), engine.summary().clone())
+        };
+
+        let (sequential, sequential_summary) = run(false);
+        let (sharded, sharded_summary) = run(true);
+        assert_eq!(sharded, sequential);
+        assert_eq!(sharded_summary, sequential_summary);
+        assert_eq!(sharded_summary.malformed_rows, 1);
+        assert_eq!(sharded_summary.skipped_duplicate, 1);
+        assert!(sharded_summary.skipped_locked > 0);
+    }
+
+    #[test]
+    fn epsilon_clamp_zeroes_tiny_negative_residue() {
+        let residue = |mut engine: Engine| {
+            engine.process_record(raw("deposit", 1, 1, Some("1.0")));
+            engine.process_record(raw("withdrawal", 1, 2, Some("1.0")));
+            engine.process_record(raw("adjustment", 1, 3, Some("-0.00000001")));
+            engine.accounts.get(&1).unwrap().available
+        };
+        assert_eq!(residue(Engine::default()), Decimal::from_str("-0.00000001").unwrap());
+        let epsilon = Decimal::from_str("0.000001").unwrap();
+        let clamped = residue(Engine::default().with_epsilon_clamp(epsilon));
+        assert_eq!(clamped, Decimal::ZERO);
+        assert!(!clamped.is_sign_negative());
+
+        let mut engine = Engine::default().with_epsilon_clamp(epsilon);
+        engine.process_record(raw("adjustment", 1, 1, Some("-0.5")));
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available,
+            Decimal::from_str("-0.5").unwrap()
+        );
+    }
+
+    #[test]
+    fn deposit_withdrawn_in_full_is_flagged() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("100.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("100.0")));
+        engine.process_record(raw("deposit", 2, 3, Some("100.0")));
+        engine.process_record(raw("withdrawal", 2, 4, Some("50.0")));
+        engine.process_record(raw("deposit", 3, 5, Some("10.0")));
+        engine.process_record(raw("withdrawal", 3, 50, Some("10.0")));
+
+        assert_eq!(
+            engine.suspicious_patterns(),
+            [SuspiciousPattern::RapidWithdrawal {
+                client: 1,
+                cycles: vec![(1, 2)]
+            }]
+        );
+    }
+
+    #[test]
+    fn suspicious_pattern_thresholds_are_configurable() {
+        let mut engine = Engine::default().with_suspicious_pattern_thresholds(SuspiciousPatternThresholds {
+            max_tx_gap: 100,
+            min_amount: Decimal::from(5),
+            min_cycles: 2,
+        });
+        for (client, tx) in [(1, 1), (1, 3), (2, 5), (3, 7)] {
+            engine.process_record(raw("deposit", client, tx, Some("10.0")));
+            engine.process_record(raw("withdrawal", client, tx + 1, Some("10.0")));
+        }
+        engine.process_record(raw("deposit", 3, 9, Some("1.0")));
+        engine.process_record(raw("withdrawal", 3, 10, Some("1.0")));
+
+        assert_eq!(
+            engine.suspicious_patterns(),
+            [SuspiciousPattern::RapidWithdrawal {
+                client: 1,
+                cycles: vec![(1, 2), (3, 4)]
+            }]
+        );
+    }
+
+    #[test]
+    fn movements_follow_their_direction() {
+        let mut engine = Engine::default();
+        let input = "type,client,tx,amount,direction\nmovement,1,1,5.0,credit\nmovement,1,2,2.0,debit\n\
+                     movement,1,3,1.0,sideways\nmovement,1,4,1.0,\n";
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("3.0").unwrap());
+        assert_eq!(account.lifetime_deposited, Decimal::from_str("5.0").unwrap());
+        assert_eq!(account.lifetime_withdrawn, Decimal::from_str("2.0").unwrap());
+        assert_eq!(engine.summary().malformed_rows, 2);
+    }
+
+    #[test]
+    fn movements_without_direction_column_are_rejected() {
+        let mut engine = Engine::default();
+        engine
+            .apply_transactions("type,client,tx,amount\nmovement,1,1,5.0\ndeposit,1,2,1.0\n".as_bytes())
+            .unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().available, Decimal::ONE);
+        assert_eq!(engine.summary().malformed_rows, 1);
+    }
+
+    #[test]
+    fn amounts_beyond_max_input_scale_are_rejected() {
+        let mut engine = Engine::default().with_max_input_scale(4);
+        let parsed = engine.parse_transaction(RawInputTransaction {
+            tx_type: "deposit".to_string(),
+            client: 1,
+            tx: 1,
+            amount: Some("1.12345".to_string()),
+            currency: None,
+            description: None,
+            amount_whole: None,
+            amount_frac: None,
+            direction: None,
+            timestamp: None,
+        });
+        assert!(matches!(parsed, Err(EngineError::InvalidAmount(_))));
+
+        let input = "type,client,tx,amount\ndeposit,1,1,1.12345\ndeposit,1,2,1.1234\n";
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("1.1234").unwrap());
+        assert_eq!(engine.summary().malformed_rows, 1);
+    }
+
+    #[test]
+    fn applies_matching_dir_files_in_lexical_order() {
+        let dir = std::env::temp_dir().join(format!("payments_engine_dir_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("2024-01-02.csv"), "type,client,tx,amount\ndispute,1,1,\n").unwrap();
+        std::fs::write(dir.join("2024-01-01.csv"), "type,client,tx,amount\ndeposit,1,1,5.0\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "type,client,tx,amount\nwithdrawal,1,2,5.0\n").unwrap();
+
+        let mut engine = Engine::default();
+        let result = engine.apply_transactions_from_dir(&dir, "*.csv");
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::ZERO);
+        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
+        assert!(glob_matches("2024-??-*.csv", "2024-01-02.csv"));
+        assert!(!glob_matches("*.csv", "notes.txt"));
+    }
+
+    #[test]
+    fn dir_errors_name_the_failing_file() {
+        let dir = std::env::temp_dir().join(format!("payments_engine_dir_errors_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("2024-01-01.csv"), "type,client,tx,amount\ndeposit,1,1,5.0\n").unwrap();
+        std::fs::write(dir.join("2024-01-02.csv"), "kind,client,tx,amount\ndeposit,1,2,5.0\n").unwrap();
+
+        let mut engine = Engine::default().with_strict_headers(true);
+        let result = engine.apply_transactions_from_dir(&dir, "*.csv");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let Err(EngineError::File { path, file_error }) = result else {
+            panic!("expected a per-file error, got {result:?}");
+        };
+        assert_eq!(path, dir.join("2024-01-02.csv"));
+        assert!(matches!(*file_error, EngineError::InputValidation(_)));
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(5));
+    }
+
+    #[test]
+    fn process_iter_steps_one_transaction_at_a_time() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,9.0\ndeposit,1,x,1.0\n";
+        let mut engine = Engine::default();
+        let mut steps = engine.process_iter(input.as_bytes());
+        assert!(matches!(steps.next(), Some(Ok(TransactionOutcome::Applied))));
+        assert!(matches!(
+            steps.next(),
+            Some(Ok(TransactionOutcome::SkippedInsufficientFunds))
+        ));
+        assert!(matches!(steps.next(), Some(Err(EngineError::Csv(_)))));
+        assert!(steps.next().is_none());
+        drop(steps);
+
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(5));
+        assert_eq!(engine.summary().malformed_rows, 1);
+    }
+
+    #[test]
+    fn bloom_dedup_has_no_false_negatives() {
+        let mut engine = Engine::default()
+            .with_bloom_dedup(true)
+            .with_bloom_sizing(20_000, 0.001);
+        for tx in 0..20_000 {
+            engine.process_record(raw("deposit", 1, tx * 7, Some("1.0")));
+        }
+        let applied = engine.summary().applied;
+        assert!(applied > 19_900);
+        assert!(engine.transaction_ids_processed.is_empty());
+
+        for tx in 0..20_000 {
+            assert_eq!(
+                engine.apply(InputTransaction::Deposit(
+                    TransactionIds { client: 2, tx: tx * 7 },
+                    Decimal::ONE
+                )),
+                TransactionOutcome::SkippedDuplicate
+            );
+        }
+        assert_eq!(engine.summary().applied, applied);
+    }
+
+    #[test]
+    fn column_precision_applies_per_column() {
+        let mut engine = Engine::default()
+            .with_column_precision(ColumnPrecision {
+                total: 2,
+                ..ColumnPrecision::default()
+            })
+            .with_zero_display(ZeroDisplay::FixedScale);
+        engine.process_record(raw("deposit", 1, 1, Some("1.23456")));
+        let mut output = Vec::new();
+        engine.write_accounts(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.2346,0.0000,1.23,false\n"
+        );
+    }
+
+    #[test]
+    fn zero_balances_never_print_negative() {
+        let write = |display| {
+            let mut engine = Engine::default().with_zero_display(display);
+            engine.process_record(raw("deposit", 1, 1, Some("2.5")));
+            engine.process_record(raw("dispute", 1, 1, None));
+            engine.process_record(raw("resolve", 1, 1, None));
+            engine.process_record(raw("adjustment", 1, 2, Some("-2.50001")));
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            write(ZeroDisplay::Normalized),
+            "client,available,held,total,locked\n1,0,0,0,false\n"
+        );
+        assert_eq!(
+            write(ZeroDisplay::FixedZero),
+            "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n"
+        );
+        assert_eq!(
+            format_decimal(Decimal::from_str("1.5").unwrap(), OUTPUT_SCALE, ZeroDisplay::FixedZero),
+            "1.5"
+        );
+        assert_eq!(
+            format_decimal(Decimal::from_str("1.5").unwrap(), OUTPUT_SCALE, ZeroDisplay::FixedScale),
+            "1.5000"
+        );
+    }
+
+    #[test]
+    fn transaction_in_other_currency_is_rejected() {
+        let input = "type,client,tx,amount,currency\n\
+                     deposit,1,1,5.0,USD\n\
+                     deposit,1,2,3.0,EUR\n\
+                     withdrawal,1,3,1.0,USD\n\
+                     deposit,2,4,2.0,\n";
+        let mut engine = Engine::default().with_output_order(OutputOrder::Sorted);
+        engine.apply_transactions(input.as_bytes()).unwrap();
+
+        assert_eq!(engine.summary().skipped_currency_mismatch, 1);
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available,
+            Decimal::from_str("4.0").unwrap()
+        );
+        let mut output = Vec::new();
+        engine.write_accounts(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,currency\n1,4,0,4,false,USD\n2,2,0,2,false,\n"
+        );
+    }
+
+    #[test]
+    fn debug_output_summarizes_engine() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+        engine.process_record(raw("deposit", 2, 2, Some("3.0")));
+        engine.process_record(raw("withdrawal", 2, 3, Some("1.0")));
+
+        assert_eq!(
+            format!("{engine:?}"),
+            "Engine { accounts: 2, transactions: 3, locked: 0, system_total: 4.0, .. }"
+        );
+    }
+
+    #[test]
+    fn output_is_flushed_every_n_rows() {
+        #[derive(Default)]
+        struct CountingWriter {
+            flushes: usize,
+        }
+
+        impl Write for CountingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushes += 1;
+                Ok(())
+            }
+        }
+
+        let flushes = |mut engine: Engine| {
+            for client in 1..=5 {
+                engine.process_record(raw("deposit", client, client.into(), Some("1.0")));
+            }
+            let mut writer = CountingWriter::default();
+            engine.write_accounts(&mut writer).unwrap();
+            writer.flushes
+        };
+
+        let at_end = flushes(Engine::default());
+        assert_eq!(flushes(Engine::default().with_flush_every(2)), at_end + 2);
+        assert_eq!(flushes(Engine::default().with_flush_every(0)), at_end);
+    }
+
+    #[test]
+    fn output_quoting_is_configurable() {
+        let mut engine = Engine::default().with_output_quoting(QuoteStyle::Always);
+        engine.process_record(raw("deposit", 1, 1, Some("1.5")));
+        let mut output = Vec::new();
+        engine.write_accounts(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\"client\",\"available\",\"held\",\"total\",\"locked\"\n\"1\",\"1.5\",\"0\",\"1.5\",\"false\"\n"
+        );
+    }
+
+    #[test]
+    fn output_flush_failure_is_distinguished() {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("1.0")));
+        let result = engine.write_accounts(FailingWriter);
+        assert!(matches!(result, Err(EngineError::Flush(error)) if error.to_string() == "disk full"));
+    }
+
+    #[test]
+    fn reader_io_error_mid_stream_is_fatal() {
+        struct FailingReader(&'static [u8]);
+
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::other("connection reset"));
+                }
+                self.0.read(buf)
+            }
+        }
+
+        let mut engine = Engine::default();
+        let result = engine.apply_transactions(FailingReader(b"type,client,tx,amount\ndeposit,1,1,1.0\n"));
+        assert!(matches!(result, Err(EngineError::Io(_))));
+        assert_eq!(engine.summary().applied, 1);
+        assert_eq!(engine.summary().malformed_rows, 0);
+    }
+
+    #[test]
+    fn resolve_before_dispute_is_counted_when_tracked() {
+        for tracked in [false, true] {
+            let mut engine = Engine::default().with_lifecycle_order_tracking(tracked);
+            engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+            engine.process_record(raw("resolve", 1, 1, None));
+            engine.process_record(raw("chargeback", 1, 1, None));
+            engine.process_record(raw("dispute", 1, 1, None));
+            engine.process_record(raw("resolve", 1, 1, None));
+
+            assert_eq!(engine.summary().out_of_order_lifecycle, 2 * usize::from(tracked));
+            assert_eq!(engine.summary().ignored, 2);
+        }
+    }
+
+    #[test]
+    fn configured_engine_seeded_in_one_expression() {
+        let mut engine = Engine::default()
+            .with_minimum_balance(Decimal::from(2))
+            .with_seeded_held(true)
+            .with_seeded_accounts("client,available,held,total,locked\n1,5,1,6,false\n".as_bytes())
+            .unwrap();
+        assert_eq!(
+            engine.try_withdraw(1, 1, Decimal::from(4)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.try_withdraw(1, 2, Decimal::from(3)), Ok(()));
+
+        let account = engine.account(1).unwrap();
+        assert_eq!((account.available, account.held), (Decimal::from(2), Decimal::ONE));
+    }
+
+    #[test]
+    fn seeding_rejects_inconsistent_totals() {
+        let mut engine = Engine::default();
+        let result = engine.seed_from_accounts_csv(
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,1,0,3,false\n".as_bytes(),
+        );
+
+        assert!(matches!(result, Err(EngineError::InputValidation(_))));
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available,
+            Decimal::from_str("1.5").unwrap()
+        );
+        assert!(!engine.accounts.contains_key(&2));
+    }
+
+    #[test]
+    fn seeding_applies_client_admission_and_rejects_held_funds() {
+        let seed = |engine: &mut Engine, rows: &str| {
+            engine.seed_from_accounts_csv(format!("client,available,held,total,locked\n{rows}").as_bytes())
+        };
+
+        let mut engine = Engine::default().with_reserved_clients(0..=9);
+        assert!(matches!(
+            seed(&mut engine, "5,1,0,1,false\n"),
+            Err(EngineError::InputValidation(_))
+        ));
+        assert!(engine.accounts.is_empty());
+
+        let mut engine = Engine::default().with_max_clients(1);
+        assert!(matches!(
+            seed(&mut engine, "1,1,0,1,false\n1,2,0,2,false\n2,1,0,1,false\n"),
+            Err(EngineError::InputValidation(_))
+        ));
+        assert_eq!(engine.accounts.get(&1).unwrap().available, Decimal::TWO);
+        assert!(!engine.accounts.contains_key(&2));
+
+        let mut engine = Engine::default();
+        assert!(matches!(
+            seed(&mut engine, "1,1,1,2,false\n"),
+            Err(EngineError::InputValidation(_))
+        ));
+        let mut engine = Engine::default().with_seeded_held(true);
+        seed(&mut engine, "1,1,1,2,false\n").unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().held, Decimal::ONE);
+    }
+
+    #[test]
+    fn total_mismatch_policy_controls_inconsistent_seeds() {
+        let seed = "client,available,held,total,locked\n1,1,1,3,false\n";
+        let seeded = |policy| {
+            let mut engine = Engine::default()
+                .with_total_mismatch_policy(policy)
+                .with_seeded_held(true);
+            engine
+                .seed_from_accounts_csv(seed.as_bytes())
+                .map(|()| engine.account(1).unwrap())
+        };
+
+        assert!(matches!(
+            seeded(TotalMismatchPolicy::Reject),
+            Err(EngineError::InputValidation(_))
+        ));
+        let recomputed = seeded(TotalMismatchPolicy::Recompute).unwrap();
+        assert_eq!((recomputed.available, recomputed.total), (Decimal::ONE, Decimal::TWO));
+        let trusted = seeded(TotalMismatchPolicy::TrustTotal).unwrap();
+        assert_eq!((trusted.available, trusted.total), (Decimal::TWO, Decimal::from(3)));
+    }
+
+    #[test]
+    fn split_amount_columns_are_combined() {
+        let input = "type,client,tx,amount_whole,amount_frac\ndeposit,1,1,5,25\ndeposit,1,2,1,05\n";
+        let mut engine = Engine::default()
+            .with_split_amount_columns(true)
+            .with_strict_headers(true);
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available,
+            Decimal::from_str("6.30").unwrap()
+        );
+
+        let mut engine = Engine::default();
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.summary().malformed_rows, 2);
+    }
+
+    #[test]
+    fn timing_reports_parse_and_apply_time() {
+        let input: String = std::iter::once("type,client,tx,amount\n".to_string())
+            .chain((1..=1000).map(|tx| format!("deposit,{},{tx},1.5\n", tx % 10)))
+            .collect();
+        let mut engine = Engine::default();
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!((engine.summary().parse_nanos, engine.summary().apply_nanos), (0, 0));
+
+        let mut engine = Engine::default().with_timing(true);
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert!(engine.summary().parse_nanos > 0);
+        assert!(engine.summary().apply_nanos > 0);
+    }
+
+    #[test]
+    fn apply_and_chargeback_locks_account() {
+        let mut engine = Engine::default();
+        engine.apply_and_chargeback(1, 1, Decimal::from(5)).unwrap();
+        let account = engine.account(1).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.available, Decimal::ZERO);
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.total, Decimal::ZERO);
+
+        assert_eq!(
+            engine.apply_and_resolve(1, 2, Decimal::from(5)),
+            Err(TransactionError::Locked)
+        );
+    }
+
+    #[test]
+    fn dispute_after_its_deposit_in_a_batch_applies() {
+        let ids = TransactionIds { client: 1, tx: 1 };
+        let mut engine = Engine::default();
+        let outcomes = engine.apply_batch([
+            InputTransaction::Deposit(ids, Decimal::ONE),
+            InputTransaction::Dispute(ids),
+        ]);
+        assert_eq!(outcomes, [TransactionOutcome::Applied, TransactionOutcome::Applied]);
+        assert_eq!(engine.account(1).unwrap().held, Decimal::ONE);
+    }
+
+    #[test]
+    fn buffered_dispute_before_its_deposit_applies_after_batch() {
+        let ids = TransactionIds { client: 1, tx: 1 };
+        let batch = [
+            InputTransaction::Dispute(ids),
+            InputTransaction::Deposit(ids, Decimal::ONE),
+            InputTransaction::Dispute(TransactionIds { client: 1, tx: 9 }),
+        ];
+        let mut engine = Engine::default();
+        assert_eq!(engine.apply_batch(batch)[0], TransactionOutcome::Ignored);
+        assert_eq!(engine.account(1).unwrap().held, Decimal::ZERO);
+
+        let mut engine = Engine::default().with_dispute_buffering(true);
+        assert_eq!(
+            engine.apply_batch(batch),
+            [
+                TransactionOutcome::Applied,
+                TransactionOutcome::Applied,
+                TransactionOutcome::Ignored
+            ]
+        );
+        assert_eq!(engine.account(1).unwrap().held, Decimal::ONE);
+
+        let input = "type,client,tx,amount\ndispute,2,5,\ndeposit,2,5,3.0\n";
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.account(2).unwrap().held, Decimal::from(3));
+    }
+
+    #[test]
+    fn max_clients_skips_new_clients_once_reached() {
+        let mut engine = Engine::default().with_max_clients(2);
+        engine.process_record(raw("deposit", 1, 1, Some("1.0")));
+        engine.process_record(raw("deposit", 2, 2, Some("1.0")));
+        assert_eq!(
+            engine.try_deposit(3, 3, Decimal::ONE),
+            Err(TransactionError::ClientLimit)
+        );
+        engine.process_record(raw("deposit", 1, 4, Some("1.0")));
+
+        assert_eq!(engine.account_count(), 2);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(2));
+        assert_eq!(engine.summary().skipped_client_limit, 1);
+    }
+
+    #[test]
+    fn blank_and_comment_lines_are_skipped() {
+        let input = "type,client,tx,amount\n\
+                     # opening balance\n\
+                     deposit,1,1,5.0\n\
+                     \n   \n\
+                     # correction\n\
+                     withdrawal,1,2,2.0\n";
+        let mut engine = Engine::default().with_comment_char(Some(b'#'));
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.summary().applied, 2);
+        assert_eq!(engine.summary().malformed_rows, 0);
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(3));
+
+        let mut engine = Engine::default();
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.summary().malformed_rows, 2);
+    }
+
+    #[test]
+    fn trailing_blank_records_are_ignored_without_warning() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use tracing::{Event, Level, Subscriber};
+        use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+        #[derive(Clone, Default)]
+        struct WarnCount(Arc<AtomicUsize>);
+
+        impl<S: Subscriber> Layer<S> for WarnCount {
+            fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+                if *event.metadata().level() <= Level::WARN {
+                    self.0.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        for ending in ["\n", "\n\n", "\r\n\r\n", "\n   \n", "\n,,,\n", "\n , , ,\n"] {
+            let input = format!("type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,2.0{ending}");
+            let warnings = WarnCount::default();
+            let subscriber = tracing_subscriber::registry().with(warnings.clone());
+            let engine = tracing::subscriber::with_default(subscriber, || {
+                let mut engine = Engine::default().with_trim(Trim::None);
+                engine.apply_transactions(input.as_bytes()).unwrap();
+                engine
+            });
+            assert_eq!(warnings.0.load(Ordering::Relaxed), 0, "{ending:?}");
+            assert_eq!(engine.summary().malformed_rows, 0, "{ending:?}");
+            assert_eq!(engine.account(1).unwrap().available, Decimal::from(3), "{ending:?}");
+        }
+    }
+
+    #[test]
+    fn high_precision_balances_round_trip_within_output_scale() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("1.23456789")));
+        engine.process_record(raw("deposit", 1, 2, Some("0.00004999")));
+        engine.process_record(raw("dispute", 1, 2, None));
+        engine.process_record(raw("deposit", 2, 3, Some("0.99999")));
+        engine.process_record(raw("withdrawal", 2, 4, Some("0.99998")));
+        engine.process_record(raw("deposit", 3, 5, Some("79228162514264.3375")));
+        engine.verify_roundtrip().unwrap();
+
+        let engine = engine
+            .with_zero_display(ZeroDisplay::FixedScale)
+            .with_reconciled_totals(true);
+        engine.verify_roundtrip().unwrap();
+    }
+
+    #[test]
+    fn stale_disputes_auto_resolve_on_finalize() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,0\n\
+                     deposit,1,2,3.0,0\n\
+                     dispute,1,1,,50\n\
+                     dispute,1,2,,3500\n\
+                     deposit,2,3,1.0,3700\n";
+        let mut engine = Engine::default().with_dispute_auto_resolve(Duration::from_hours(1));
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.account(1).unwrap().held, Decimal::from(8));
+
+        let finalized = engine.finalize();
+        let account = finalized.account(1).unwrap();
+        assert_eq!((account.available, account.held), (Decimal::from(5), Decimal::from(3)));
+        assert_eq!(finalized.transaction_state(1, 1), Some(PublicTxState::Resolved));
+        assert_eq!(finalized.transaction_state(1, 2), Some(PublicTxState::Disputed));
+        assert_eq!(finalized.summary().auto_resolved_disputes, 1);
+    }
+
+    #[test]
+    fn repeated_dispute_rows_are_coalesced() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use tracing::{Event, Level, Subscriber};
+        use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+        #[derive(Clone, Default)]
+        struct WarnCount(Arc<AtomicUsize>);
+
+        impl<S: Subscriber> Layer<S> for WarnCount {
+            fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+                if *event.metadata().level() <= Level::WARN {
+                    self.0.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,1,\ndispute,1,1,\ndispute,1,1,\n";
+        let warnings = WarnCount::default();
+        let subscriber = tracing_subscriber::registry().with(warnings.clone());
+        let engine = tracing::subscriber::with_default(subscriber, || {
+            let mut engine = Engine::default().with_dispute_coalescing(10);
+            engine.apply_transactions(input.as_bytes()).unwrap();
+            engine
+        });
+        assert_eq!(warnings.0.load(Ordering::Relaxed), 1);
+        let account = engine.account(1).unwrap();
+        assert_eq!((account.available, account.held), (Decimal::ZERO, Decimal::from(5)));
+        assert_eq!(engine.summary().applied, 2);
+        assert_eq!(engine.summary().coalesced_dispute_steps, 2);
+        assert_eq!(engine.summary().duplicate_dispute_attempts, 0);
+    }
+
+    #[test]
+    fn dispute_before_its_deposit_does_not_swallow_the_real_dispute() {
+        let input = "type,client,tx,amount\ndispute,1,1,\ndeposit,1,1,5.0\ndispute,1,1,\n";
+        let mut engine = Engine::default().with_dispute_coalescing(10);
+        engine.apply_transactions(input.as_bytes()).unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert_eq!((account.available, account.held), (Decimal::ZERO, Decimal::from(5)));
+        assert_eq!(engine.summary().coalesced_dispute_steps, 0);
+    }
+
+    #[test]
+    fn dispute_steps_outside_the_window_are_not_coalesced() {
+        let mut engine = Engine::default().with_dispute_coalescing(1);
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("5.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("deposit", 1, 3, Some("5.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+        assert_eq!(engine.summary().coalesced_dispute_steps, 0);
+        assert_eq!(engine.summary().duplicate_dispute_attempts, 1);
+    }
+
+    #[test]
+    fn trim_setting_controls_memo_whitespace() {
+        let input = "type,client,tx,amount,description\ndeposit,1,1,1.0,  spaced memo  \n";
+        let memo = |mut engine: Engine| {
+            engine.apply_transactions(input.as_bytes()).unwrap();
+            engine.accounts.get(&1).unwrap().description(1).map(str::to_owned)
+        };
+        assert_eq!(memo(Engine::default()).as_deref(), Some("spaced memo"));
+        assert_eq!(
+            memo(Engine::default().with_trim(Trim::None)).as_deref(),
+            Some("  spaced memo  ")
+        );
+    }
+
+    #[test]
+    fn undo_last_reverts_applied_transactions() {
+        let mut engine = Engine::default().with_undo(2);
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("3.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+
+        engine.undo_last().unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from(8));
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(matches!(account.deposit_state(1), Some(TransactionState::Normal)));
+
+        engine.undo_last().unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap().available, Decimal::from(5));
+        assert!(!engine.transaction_ids_processed.contains(&2));
+        // Only two steps were kept.
+        assert_eq!(engine.undo_last(), Err(UndoError::NothingToUndo));
+        assert_eq!(Engine::default().undo_last(), Err(UndoError::Disabled));
+    }
+
+    #[test]
+    fn undo_last_reverts_summary_and_velocity() {
+        let mut engine = Engine::default()
+            .with_undo(1)
+            .with_withdrawal_velocity_limit(1, Decimal::MAX);
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        assert_eq!(engine.take_deltas().len(), 1);
+        engine.process_record(raw("withdrawal", 1, 2, Some("1.0")));
+        let before_undo = engine.summary.clone();
+
+        engine.undo_last().unwrap();
+        assert_eq!(engine.summary.applied, before_undo.applied - 1);
+        // The undone withdrawal no longer counts towards the velocity limit.
+        assert!(matches!(
+            engine.process_record(raw("withdrawal", 1, 3, Some("2.0"))),
+            TransactionOutcome::Applied
+        ));
+        assert_eq!(engine.summary.skipped_velocity_limit, 0);
+        // The delta baseline isn't reverted, so only the applied withdrawal shows up.
+        assert_eq!(engine.take_deltas()[&1].available, Decimal::from(-2));
+    }
+
+    #[test]
+    fn take_deltas_reports_changes_since_last_call() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("deposit", 2, 2, Some("3.0")));
+        assert_eq!(engine.take_deltas().len(), 2);
+
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+        engine.process_record(raw("deposit", 3, 3, Some("1.0")));
+        let deltas = engine.take_deltas();
+
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(
+            deltas[&1],
+            AccountDelta {
+                available: Decimal::from(-5),
+                held: Decimal::ZERO,
+                locked: Some(true),
+            }
+        );
+        assert_eq!(deltas[&3].available, Decimal::from(1));
+        assert!(engine.take_deltas().is_empty());
+    }
+
+    #[test]
+    fn take_deltas_saturates_changes_past_decimal_range() {
+        let mut engine = Engine::default();
+        engine.try_deposit(1, 1, Decimal::MAX).unwrap();
+        engine.take_deltas();
+        engine.try_withdraw(1, 2, Decimal::MAX).unwrap();
+        engine.process_record(raw("dispute", 1, 1, None));
+
+        let deltas = engine.take_deltas();
+        assert_eq!(engine.accounts.get(&1).unwrap().available, Decimal::MIN);
+        assert_eq!(deltas[&1].available, Decimal::MIN);
+        assert_eq!(deltas[&1].held, Decimal::MAX);
+    }
+
+    #[test]
+    fn input_checksum_depends_on_applied_order() {
+        let checksum = |rows: &[(&str, ClientId, TransactionId, Option<&str>)]| {
+            let mut engine = Engine::default();
+            for (kind, client, tx, amount) in rows {
+                engine.process_record(raw(kind, *client, *tx, *amount));
+            }
+            engine.input_checksum()
+        };
+        let rows = [("deposit", 1, 1, Some("5.0")), ("withdrawal", 1, 2, Some("2.0"))];
+        assert_eq!(checksum(&rows), checksum(&rows));
+        assert_ne!(checksum(&rows), 0);
+        assert_ne!(checksum(&rows), checksum(&[rows[1], rows[0]]));
+    }
+
+    #[test]
+    fn duplicate_header_is_flagged() {
+        let input = "type,client,tx,amount,amount\ndeposit,1,1,1.0,2.0\n";
+        let mut engine = Engine::default().with_strict_headers(true);
+        let result = engine.apply_transactions(input.as_bytes());
+        assert!(matches!(result, Err(EngineError::InputValidation(problem)) if problem.contains("duplicate")));
+        assert_eq!(engine.account_count(), 0);
+
+        let extra_column = "type,client,tx,amount,signature\ndeposit,1,1,1.0,abc\n";
+        assert!(check_headers(
+            &csv::StringRecord::from(vec!["type", "client", "tx", "amount", "currency"]),
+            &AMOUNT_COLUMNS
+        )
+        .is_ok());
+        let mut lenient = Engine::default();
+        lenient.apply_transactions(extra_column.as_bytes()).unwrap();
+        assert_eq!(lenient.account_count(), 1);
+        let mut strict = Engine::default().with_strict_headers(true);
+        assert!(strict.apply_transactions(extra_column.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn comma_decimal_separator_needs_locale() {
+        let deposit = || RawInputTransaction {
+            tx_type: "deposit".to_string(),
+            client: 1,
+            tx: 1,
+            amount: Some("1,50".to_string()),
+            currency: None,
+            description: None,
+            amount_whole: None,
+            amount_frac: None,
+            direction: None,
+            timestamp: None,
+        };
+        let result = Engine::default().parse_transaction(deposit());
+        assert!(matches!(result, Err(EngineError::InvalidAmount(message)) if message.contains("comma separator")));
+        // The locale alone doesn't enable it.
+        let engine = Engine::default().with_decimal_comma(true);
+        assert!(engine.parse_transaction(deposit()).is_err());
+
+        let engine = engine.with_lenient_amount_parsing(true);
+        let transaction = engine.parse_transaction(deposit()).unwrap();
+        assert_eq!(transaction.amount(), Some(Decimal::from_str("1.50").unwrap()));
+    }
+
+    #[test]
+    fn processed_transaction_ids_lists_claimed_ids() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("5.0")));
+        engine.process_record(raw("withdrawal", 1, 2, Some("2.0")));
+        engine.process_record(raw("withdrawal", 2, 3, Some("1.0")));
+        engine.process_record(raw("dispute", 1, 1, None));
+
+        let mut ids: Vec<_> = engine.processed_transaction_ids().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn scientific_amounts_need_lenient_parsing() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5e2\ndeposit,1,2,1e99999\n";
+        let mut strict = Engine::default();
+        strict.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(strict.summary().malformed_rows, 2);
+
+        let mut lenient = Engine::default().with_lenient_amount_parsing(true);
+        lenient.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(lenient.summary().malformed_rows, 1);
+        assert_eq!(lenient.accounts.get(&1).unwrap().available, Decimal::from(150));
+    }
+
+    #[test]
+    fn missing_amount_rejected_by_default() {
+        let mut engine = Engine::default();
+        engine
+            .apply_transactions("type,client,tx,amount\ndeposit,1,1,\n".as_bytes())
+            .unwrap();
+        assert_eq!(engine.summary().malformed_rows, 1);
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[test]
+    fn negative_deposit_applied_as_withdrawal_when_enabled() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,-2.0\ndeposit,1,3,-10.0\n";
+
+        let mut engine = Engine::default().with_negative_deposit_as_withdrawal(true);
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available, Decimal::from(3));
+        assert_eq!(account.lifetime_withdrawn, Decimal::from(2));
+        assert_eq!(engine.summary().skipped_insufficient_funds, 1);
+
+        let mut engine = Engine::default();
+        engine.apply_transactions(input.as_bytes()).unwrap();
+        assert_eq!(engine.account(1).unwrap().available, Decimal::from(5));
+        assert_eq!(engine.summary().skipped_invalid_amount, 2);
+    }
+
+    #[test]
+    fn missing_amount_treated_as_zero() {
+        let mut engine = Engine::default().with_missing_amount_policy(MissingAmountPolicy::TreatAsZero);
+        engine
+            .apply_transactions("type,client,tx,amount\ndeposit,1,1,\n".as_bytes())
+            .unwrap();
+        assert_eq!(engine.summary().malformed_rows, 0);
+        assert_eq!(engine.summary().skipped_invalid_amount, 1);
+    }
+
+    #[test]
+    fn locked_accounts_lists_charged_back_clients() {
+        let mut engine = Engine::default();
+        for client in [3, 1, 2] {
+            let tx = u32::from(client);
+            engine.process_record(raw("deposit", client, tx, Some("1.0")));
+            if client != 2 {
+                engine.process_record(raw("dispute", client, tx, None));
+                engine.process_record(raw("chargeback", client, tx, None));
+            }
+        }
+
+        assert_eq!(engine.locked_accounts(), [1, 3]);
+    }
+
+    #[test]
+    fn deposit_reusing_charged_back_id_is_counted_in_strict_mode() {
+        for strict in [false, true] {
+            let mut engine = Engine::default().with_strict_state_transitions(strict);
+            engine.process_record(raw("deposit", 1, 1, Some("2.0")));
+            engine.process_record(raw("dispute", 1, 1, None));
+            engine.process_record(raw("chargeback", 1, 1, None));
+            engine.process_record(raw("deposit", 2, 2, Some("1.0")));
+
+            assert_eq!(
+                engine.apply(raw("deposit", 2, 1, Some("5.0"))),
+                TransactionOutcome::SkippedDuplicate
+            );
+            assert_eq!(
+                engine.apply(raw("deposit", 2, 2, Some("5.0"))),
+                TransactionOutcome::SkippedDuplicate
+            );
+            assert_eq!(engine.summary().charged_back_id_reuse, usize::from(strict));
+            assert_eq!(engine.summary().skipped_duplicate, 2);
+            assert_eq!(engine.accounts.get(&2).unwrap().available, Decimal::ONE);
+        }
+    }
+
+    #[test]
+    fn per_transaction_spans_carry_transaction_fields() {
+        use std::{
+            fmt::{self, Write as _},
+            sync::{Arc, Mutex},
+        };
+
+        use tracing::{
+            field::{Field, Visit},
+            span, Subscriber,
+        };
+        use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+        #[derive(Clone, Default)]
+        struct SpanCapture(Arc<Mutex<Vec<String>>>);
+
+        struct FieldWriter(String);
+
+        impl Visit for FieldWriter {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                write!(self.0, " {}={value:?}", field.name()).unwrap();
+            }
+        }
+
+        impl<S: Subscriber> Layer<S> for SpanCapture {
+            fn on_new_span(&self, attrs: &span::Attributes<'_>, _id: &span::Id, _ctx: Context<'_, S>) {
+                let mut fields = FieldWriter(attrs.metadata().name().to_string());
+                attrs.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+        }
+
+        let capture = SpanCapture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let mut engine = Engine::default();
+            engine.process_record(raw("deposit", 1, 1, Some("1.0")));
+            let mut engine = engine.with_per_transaction_spans(true);
+            engine.process_record(raw("deposit", 7, 42, Some("1.0")));
+        });
+
+        assert_eq!(
+            *capture.0.lock().unwrap(),
+            ["transaction client=7 tx=42 kind=\"deposit\""]
+        );
+    }
+
+    #[test]
+    fn reverse_chargeback_restores_funds_and_lock() {
+        let mut engine = Engine::default();
+        engine.process_record(raw("deposit", 1, 1, Some("3.0")));
+        engine.process_record(raw("deposit", 1, 2, Some("1.0")));
+        assert_eq!(
+            engine.reverse_chargeback(1, 1),
+            Err(TransactionError::InvalidTransition)
+        );
+        assert_eq!(engine.reverse_chargeback(1, 9), Err(TransactionError::NotApplicable));
+        assert_eq!(engine.reverse_chargeback(5, 1), Err(TransactionError::UnknownClient));
+
+        engine.process_record(raw("dispute", 1, 1, None));
+        engine.process_record(raw("chargeback", 1, 1, None));
+        assert_eq!(engine.lock_reason(1), Some(LockReason::Chargeback(1)));
+
+        assert_eq!(engine.reverse_chargeback(1, 1), Ok(()));
+        assert_eq!(
+            engine.reverse_chargeback(1, 1),
+            Err(TransactionError::InvalidTransition)
+        );
+        assert_eq!(engine.lock_reason(1), None);
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available, Decimal::from_str("4.0").unwrap());
+        assert_eq!(account.held, Decimal::ZERO);
+        assert!(!account.locked);
+        let Transaction::Deposit(deposit) = account.transactions.get(&1).unwrap() else {
+            panic!("expected deposit transaction");
+        };
+        assert!(matches!(deposit.state, TransactionState::Resolved));
+        assert_eq!(
+            engine.apply(raw("deposit", 1, 3, Some("1.0"))),
+            TransactionOutcome::Applied
+        );
+    }
+
+    fn raw(kind: &str, client: ClientId, tx: TransactionId, amount: Option<&str>) -> InputTransaction {
+        RawInputTransaction {
+            tx_type: kind.to_string(),
+            client,
+            tx,
+            amount: amount.map(str::to_owned),
+            currency: None,
+            description: None,
+            amount_whole: None,
+            amount_frac: None,
+            direction: None,
+            timestamp: None,
+        }
+        .try_into()
+        .expect("Raw transaction failed to convert into InputTransaction")
+    }
+}
@@ -0,0 +1,664 @@
+//! Transaction state machine for a single client account.
+//!
+//! Nothing in here touches CSV, files or `std`, so the ledger can be embedded on its own (WASM,
+//! embedded targets) by building with `--no-default-features`. The `std`-only `Engine` wraps these
+//! accounts with cross-client duplicate detection and the CSV/file layer.
+
+#[cfg(any(not(feature = "std"), feature = "btree"))]
+use alloc::collections::BTreeMap as TransactionMap;
+use alloc::{string::String, vec::Vec};
+#[cfg(all(feature = "std", not(feature = "btree")))]
+use std::collections::HashMap as TransactionMap;
+
+use rust_decimal::Decimal;
+
+use crate::TransactionId;
+
+/// Balances and transaction history for one client.
+#[derive(Debug, Default, Clone)]
+pub struct Account {
+    pub(crate) available: Decimal,
+    pub(crate) held: Decimal,
+    pub(crate) locked: bool,
+    pub(crate) lock_reason: Option<LockReason>,
+    // Gross inflow and outflow from applied deposits and withdrawals. Disputes, resolves and
+    // chargebacks don't change them.
+    pub(crate) lifetime_deposited: Decimal,
+    pub(crate) lifetime_withdrawn: Decimal,
+    // Running total kept alongside `available` and `held` from each transaction's net effect, as
+    // a cross-check on `total()`. Only something outside the ledger, like the engine's epsilon
+    // clamp, makes them differ.
+    pub(crate) stored_total: Decimal,
+    // Balance the account was opened with before any transactions, if it was set explicitly.
+    pub(crate) opening_balance: Option<Decimal>,
+    pub(crate) transactions: TransactionMap<TransactionId, Transaction>,
+    // Undisputed deposits as `(tx id, amount)` pairs sorted by id. Most deposits are never
+    // disputed, so they're kept in this compact form and only promoted into `transactions` once
+    // disputed. Appending is cheap when tx ids arrive in increasing order, as they usually do.
+    pub(crate) deposits: Vec<(TransactionId, Decimal)>,
+    // Free-text memos from the input, only for transactions that had one.
+    pub(crate) descriptions: TransactionMap<TransactionId, String>,
+    pub(crate) policy: AccountPolicy,
+    // Adopted from the first deposit that names one. `None` for single-currency input.
+    pub(crate) currency: Option<String>,
+}
+
+/// Why an account was locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    /// Charging back the given deposit locked the account.
+    Chargeback(TransactionId),
+    /// The client's withdrawals went over the engine's velocity limit.
+    VelocityLimit,
+}
+
+/// Limits an account enforces on every transaction applied to it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AccountPolicy {
+    /// Lowest `available` a transaction may leave behind.
+    pub min_available: Option<Decimal>,
+    /// Highest `available` a transaction may leave behind.
+    pub max_available: Option<Decimal>,
+    /// Balance a withdrawal must leave in `available`. Zero by default, so a withdrawal may empty
+    /// the account.
+    pub minimum_balance: Decimal,
+    /// Skip every withdrawal while any deposit on the account is disputed.
+    pub block_withdrawals_during_dispute: bool,
+    /// How much of a disputed deposit is moved from `available` to `held`.
+    pub dispute_hold_basis: DisputeHoldBasis,
+}
+
+/// How much a dispute holds when the disputed deposit has already been partly spent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeHoldBasis {
+    /// Hold the deposit's full amount, even if that takes `available` negative. This is the
+    /// behavior the spec describes.
+    #[default]
+    OriginalAmount,
+    /// Hold at most what's left in `available`, so a deposit of 5 followed by a withdrawal of 3
+    /// only holds 2. Nothing is held if `available` is already zero or negative.
+    RemainingAvailable,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Transaction {
+    Deposit(Deposit),
+    #[allow(dead_code)]
+    Withdrawal(Withdrawal),
+    #[allow(dead_code)]
+    Adjustment(Adjustment),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Deposit {
+    // Only the engine reads the original amount back, to report open disputes.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) amount: Decimal,
+    // What disputing the deposit moved into `held`, which resolving or charging it back releases.
+    // Equal to `amount` unless the policy's `dispute_hold_basis` held less.
+    pub(crate) held: Decimal,
+    pub(crate) state: TransactionState,
+}
+
+// Based on spec wording, assuming that withdrawals cannot be disputed, and therefore don't require
+// a state.
+#[derive(Debug, Clone)]
+pub(crate) struct Withdrawal {
+    #[allow(dead_code)]
+    pub(crate) amount: Decimal,
+}
+
+// Manual operator corrections. Like withdrawals these are never disputable, so no state is tracked.
+#[derive(Debug, Clone)]
+pub(crate) struct Adjustment {
+    #[allow(dead_code)]
+    pub(crate) amount: Decimal,
+}
+
+// Everything a single transaction can change on an account, captured before it's applied so the
+// engine can undo it.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    tx_id: TransactionId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    lock_reason: Option<LockReason>,
+    lifetime_deposited: Decimal,
+    lifetime_withdrawn: Decimal,
+    stored_total: Decimal,
+    transaction: Option<Transaction>,
+    deposit: Option<Decimal>,
+    description: Option<String>,
+    currency: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransactionState {
+    Normal,
+    Disputed,
+    // Assume that funds cannot be disputed more than once. Would be odd for a bank to allow a customer to dispute
+    // the same charge after resolving. So we'll mark resolved transactions so that they can't be disputed again.
+    Resolved,
+    ChargedBack,
+}
+
+/// What happened to a transaction handed to the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionOutcome {
+    /// The transaction changed account state.
+    Applied,
+    /// The account is locked by a chargeback, so nothing was applied.
+    SkippedLocked,
+    /// The transaction id has already been processed.
+    SkippedDuplicate,
+    /// A withdrawal exceeded the available balance.
+    SkippedInsufficientFunds,
+    /// A deposit or withdrawal amount wasn't positive.
+    SkippedInvalidAmount,
+    /// Applying the transaction would have moved `available` outside the account's configured
+    /// bounds.
+    SkippedBalanceBounds,
+    /// Applying the transaction would have overflowed `available`, `held` or their total.
+    SkippedBalanceOverflow,
+    /// A withdrawal was blocked because the account has an open dispute and the account's policy
+    /// holds withdrawals until disputes are settled.
+    SkippedOpenDispute,
+    /// The transaction named a currency other than the one its account adopted from its first
+    /// deposit.
+    SkippedCurrencyMismatch,
+    /// The transaction targeted a client id reserved for system accounts.
+    SkippedReservedClient,
+    /// The transaction would have opened an account beyond the engine's configured client limit.
+    SkippedClientLimit,
+    /// A withdrawal would have taken the client past the engine's withdrawal velocity limit.
+    SkippedVelocityLimit,
+    /// The transaction references something that doesn't apply, e.g. a dispute of an unknown or
+    /// non-deposit transaction.
+    Ignored,
+    /// A dispute, resolve or chargeback referenced a deposit in the wrong state, e.g. resolving one
+    /// that isn't disputed. The engine reports these as `Ignored` unless strict state transitions
+    /// are enabled.
+    InvalidTransition,
+    /// A dispute, resolve or chargeback named a client the engine has never seen. Only reported
+    /// with strict state transitions, otherwise it's `Ignored`.
+    UnknownClient,
+}
+
+impl AccountPolicy {
+    fn allows_available(&self, available: Decimal) -> bool {
+        self.min_available.is_none_or(|min| available >= min) && self.max_available.is_none_or(|max| available <= max)
+    }
+}
+
+impl Account {
+    #[must_use]
+    pub fn with_policy(policy: AccountPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub const fn available(&self) -> Decimal {
+        self.available
+    }
+
+    #[must_use]
+    pub const fn held(&self) -> Decimal {
+        self.held
+    }
+
+    #[must_use]
+    pub fn total(&self) -> Decimal {
+        self.available + self.held
+    }
+
+    #[must_use]
+    pub const fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    #[must_use]
+    pub const fn lock_reason(&self) -> Option<LockReason> {
+        self.lock_reason
+    }
+
+    /// Sum of every deposit applied to the account, saturating at `Decimal::MAX`.
+    #[must_use]
+    pub const fn lifetime_deposited(&self) -> Decimal {
+        self.lifetime_deposited
+    }
+
+    /// Sum of every withdrawal applied to the account, saturating at `Decimal::MAX`.
+    #[must_use]
+    pub const fn lifetime_withdrawn(&self) -> Decimal {
+        self.lifetime_withdrawn
+    }
+
+    #[must_use]
+    pub fn currency(&self) -> Option<&str> {
+        self.currency.as_deref()
+    }
+
+    /// Free-text memo attached to transaction `tx_id`, if any.
+    #[must_use]
+    pub fn description(&self, tx_id: TransactionId) -> Option<&str> {
+        self.descriptions.get(&tx_id).map(String::as_str)
+    }
+
+    /// Attaches a free-text memo to transaction `tx_id`. It has no effect on balances.
+    pub fn set_description(&mut self, tx_id: TransactionId, description: String) {
+        self.descriptions.insert(tx_id, description);
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn checkpoint(&self, tx_id: TransactionId) -> Checkpoint {
+        Checkpoint {
+            tx_id,
+            available: self.available,
+            held: self.held,
+            locked: self.locked,
+            lock_reason: self.lock_reason,
+            lifetime_deposited: self.lifetime_deposited,
+            lifetime_withdrawn: self.lifetime_withdrawn,
+            stored_total: self.stored_total,
+            transaction: self.transactions.get(&tx_id).cloned(),
+            deposit: self.undisputed_deposit(tx_id).ok().map(|index| self.deposits[index].1),
+            description: self.descriptions.get(&tx_id).cloned(),
+            currency: self.currency.clone(),
+        }
+    }
+
+    // Puts the account back the way it was when `checkpoint` was taken, assuming only the
+    // checkpoint's transaction has been applied since.
+    #[cfg(feature = "std")]
+    pub(crate) fn restore(&mut self, checkpoint: Checkpoint) {
+        let tx_id = checkpoint.tx_id;
+        self.available = checkpoint.available;
+        self.held = checkpoint.held;
+        self.locked = checkpoint.locked;
+        self.lock_reason = checkpoint.lock_reason;
+        self.lifetime_deposited = checkpoint.lifetime_deposited;
+        self.lifetime_withdrawn = checkpoint.lifetime_withdrawn;
+        self.stored_total = checkpoint.stored_total;
+        self.currency = checkpoint.currency;
+        match checkpoint.transaction {
+            Some(transaction) => self.transactions.insert(tx_id, transaction),
+            None => self.transactions.remove(&tx_id),
+        };
+        match (self.undisputed_deposit(tx_id), checkpoint.deposit) {
+            (Ok(index), Some(amount)) => self.deposits[index].1 = amount,
+            (Ok(index), None) => {
+                self.deposits.remove(index);
+            }
+            (Err(index), Some(amount)) => self.deposits.insert(index, (tx_id, amount)),
+            (Err(_), None) => {}
+        }
+        match checkpoint.description {
+            Some(description) => self.descriptions.insert(tx_id, description),
+            None => self.descriptions.remove(&tx_id),
+        };
+    }
+
+    /// Credits `amount` to `available`. Duplicate ids are only detected within this account; the
+    /// engine additionally rejects ids reused across clients.
+    pub fn deposit(&mut self, tx_id: TransactionId, amount: Decimal) -> TransactionOutcome {
+        if amount <= Decimal::ZERO {
+            return TransactionOutcome::SkippedInvalidAmount;
+        }
+        if let Err(outcome) = self.check_new_transaction(tx_id) {
+            return outcome;
+        }
+        let (available, _) = match checked_balances(self.available, self.held, amount, Decimal::ZERO) {
+            Ok(balances) => balances,
+            Err(outcome) => return outcome,
+        };
+        if let Err(outcome) = self.check_available(available) {
+            return outcome;
+        }
+
+        self.available = available;
+        self.stored_total = self.stored_total.saturating_add(amount);
+        self.lifetime_deposited = self.lifetime_deposited.saturating_add(amount);
+        if let Err(index) = self.undisputed_deposit(tx_id) {
+            self.deposits.insert(index, (tx_id, amount));
+        }
+        TransactionOutcome::Applied
+    }
+
+    /// Debits `amount` from `available` if that leaves at least the policy's minimum balance. Held
+    /// funds are never used.
+    pub fn withdraw(&mut self, tx_id: TransactionId, amount: Decimal) -> TransactionOutcome {
+        // A non-positive amount is reported as such even for a reused id.
+        if amount > Decimal::ZERO {
+            if let Err(outcome) = self.check_new_transaction(tx_id) {
+                return outcome;
+            }
+        }
+        let available = match self.withdrawal_balance(amount) {
+            Ok(available) => available,
+            Err(outcome) => return outcome,
+        };
+
+        self.available = available;
+        self.stored_total = self.stored_total.saturating_sub(amount);
+        self.lifetime_withdrawn = self.lifetime_withdrawn.saturating_add(amount);
+        self.transactions
+            .insert(tx_id, Transaction::Withdrawal(Withdrawal { amount }));
+        TransactionOutcome::Applied
+    }
+
+    /// The `available` balance withdrawing `amount` would leave under the account's policy. Neither
+    /// the transaction id nor the lock is checked.
+    ///
+    /// # Errors
+    ///
+    /// Returns the outcome the withdrawal would be skipped with.
+    pub fn withdrawal_balance(&self, amount: Decimal) -> Result<Decimal, TransactionOutcome> {
+        if amount <= Decimal::ZERO {
+            return Err(TransactionOutcome::SkippedInvalidAmount);
+        }
+        if self.policy.block_withdrawals_during_dispute && self.has_open_dispute() {
+            return Err(TransactionOutcome::SkippedOpenDispute);
+        }
+        let (available, _) = checked_balances(self.available, self.held, -amount, Decimal::ZERO)?;
+        if available < self.policy.minimum_balance {
+            return Err(TransactionOutcome::SkippedInsufficientFunds);
+        }
+        self.check_available(available)?;
+        Ok(available)
+    }
+
+    /// Sets `available` to an opening balance carried over from another system. It isn't a
+    /// deposit, so it can't be disputed. Meant for accounts without transactions yet.
+    pub fn set_opening_balance(&mut self, available: Decimal) -> TransactionOutcome {
+        let Some(total) = available.checked_add(self.held) else {
+            return TransactionOutcome::SkippedBalanceOverflow;
+        };
+        self.available = available;
+        self.stored_total = total;
+        self.opening_balance = Some(available);
+        TransactionOutcome::Applied
+    }
+
+    /// Signed operator correction applied straight to `available`. Unlike a withdrawal this is
+    /// allowed to take the account negative, since the operator is the authority on the correct
+    /// balance.
+    pub fn adjust(&mut self, tx_id: TransactionId, amount: Decimal) -> TransactionOutcome {
+        if let Err(outcome) = self.check_new_transaction(tx_id) {
+            return outcome;
+        }
+        let (available, _) = match checked_balances(self.available, self.held, amount, Decimal::ZERO) {
+            Ok(balances) => balances,
+            Err(outcome) => return outcome,
+        };
+        if let Err(outcome) = self.check_available(available) {
+            return outcome;
+        }
+
+        self.available = available;
+        self.stored_total = self.stored_total.saturating_add(amount);
+        self.transactions
+            .insert(tx_id, Transaction::Adjustment(Adjustment { amount }));
+        TransactionOutcome::Applied
+    }
+
+    /// Moves a deposit's funds from `available` to `held`, as much as the policy's
+    /// [`DisputeHoldBasis`] allows.
+    pub fn dispute(&mut self, tx_id: TransactionId) -> TransactionOutcome {
+        if self.locked {
+            return TransactionOutcome::SkippedLocked;
+        }
+        let Ok(index) = self.undisputed_deposit(tx_id) else {
+            return self.missing_deposit_outcome(tx_id);
+        };
+
+        let (_, amount) = self.deposits[index];
+        let held_amount = match self.policy.dispute_hold_basis {
+            DisputeHoldBasis::OriginalAmount => amount,
+            DisputeHoldBasis::RemainingAvailable => amount.min(self.available.max(Decimal::ZERO)),
+        };
+        let (available, held) = match checked_balances(self.available, self.held, -held_amount, held_amount) {
+            Ok(balances) => balances,
+            Err(outcome) => return outcome,
+        };
+        if !self.policy.allows_available(available) {
+            return TransactionOutcome::SkippedBalanceBounds;
+        }
+
+        self.available = available;
+        self.held = held;
+        self.deposits.remove(index);
+        self.transactions.insert(
+            tx_id,
+            Transaction::Deposit(Deposit {
+                amount,
+                held: held_amount,
+                state: TransactionState::Disputed,
+            }),
+        );
+        TransactionOutcome::Applied
+    }
+
+    /// Releases a disputed deposit's funds back to `available`.
+    pub fn resolve(&mut self, tx_id: TransactionId) -> TransactionOutcome {
+        if self.locked {
+            return TransactionOutcome::SkippedLocked;
+        }
+        let Some(Transaction::Deposit(deposit)) = self.transactions.get_mut(&tx_id) else {
+            return self.missing_deposit_outcome(tx_id);
+        };
+
+        if !matches!(deposit.state, TransactionState::Disputed) {
+            return TransactionOutcome::InvalidTransition;
+        }
+        let (available, held) = match checked_balances(self.available, self.held, deposit.held, -deposit.held) {
+            Ok(balances) => balances,
+            Err(outcome) => return outcome,
+        };
+        if !self.policy.allows_available(available) {
+            return TransactionOutcome::SkippedBalanceBounds;
+        }
+
+        self.available = available;
+        self.held = held;
+        deposit.state = TransactionState::Resolved;
+        TransactionOutcome::Applied
+    }
+
+    /// Removes a disputed deposit's held funds and locks the account.
+    pub fn chargeback(&mut self, tx_id: TransactionId) -> TransactionOutcome {
+        if self.locked {
+            return TransactionOutcome::SkippedLocked;
+        }
+        let Some(Transaction::Deposit(deposit)) = self.transactions.get_mut(&tx_id) else {
+            return self.missing_deposit_outcome(tx_id);
+        };
+
+        if !matches!(deposit.state, TransactionState::Disputed) {
+            return TransactionOutcome::InvalidTransition;
+        }
+
+        let (_, held) = match checked_balances(self.available, self.held, Decimal::ZERO, -deposit.held) {
+            Ok(balances) => balances,
+            Err(outcome) => return outcome,
+        };
+
+        self.held = held;
+        self.stored_total = self.stored_total.saturating_sub(deposit.held);
+        self.locked = true;
+        self.lock_reason = Some(LockReason::Chargeback(tx_id));
+        deposit.state = TransactionState::ChargedBack;
+        TransactionOutcome::Applied
+    }
+
+    /// Undoes an erroneous chargeback: the funds it removed go back to `available`, the deposit
+    /// is treated as resolved, and the account is unlocked if this chargeback is what locked
+    /// it.
+    pub fn reverse_chargeback(&mut self, tx_id: TransactionId) -> TransactionOutcome {
+        let Some(Transaction::Deposit(deposit)) = self.transactions.get_mut(&tx_id) else {
+            return self.missing_deposit_outcome(tx_id);
+        };
+        if !matches!(deposit.state, TransactionState::ChargedBack) {
+            return TransactionOutcome::InvalidTransition;
+        }
+
+        let (available, _) = match checked_balances(self.available, self.held, deposit.held, Decimal::ZERO) {
+            Ok(balances) => balances,
+            Err(outcome) => return outcome,
+        };
+
+        self.available = available;
+        self.stored_total = self.stored_total.saturating_add(deposit.held);
+        deposit.state = TransactionState::Resolved;
+        if self.lock_reason == Some(LockReason::Chargeback(tx_id)) {
+            self.locked = false;
+            self.lock_reason = None;
+        }
+        TransactionOutcome::Applied
+    }
+
+    pub(crate) fn deposit_state(&self, tx_id: TransactionId) -> Option<TransactionState> {
+        if self.undisputed_deposit(tx_id).is_ok() {
+            return Some(TransactionState::Normal);
+        }
+        match self.transactions.get(&tx_id)? {
+            Transaction::Deposit(deposit) => Some(deposit.state),
+            _ => None,
+        }
+    }
+
+    /// Number of transactions applied to the account, disputed or not.
+    #[must_use]
+    pub fn transaction_count(&self) -> usize {
+        self.transactions.len() + self.deposits.len()
+    }
+
+    // Index of an undisputed deposit in `deposits`, or where it would be inserted.
+    fn undisputed_deposit(&self, tx_id: TransactionId) -> Result<usize, usize> {
+        self.deposits.binary_search_by_key(&tx_id, |(id, _)| *id)
+    }
+
+    // What a dispute step gets for a transaction that isn't a deposit in the state it expects.
+    fn missing_deposit_outcome(&self, tx_id: TransactionId) -> TransactionOutcome {
+        if self.deposit_state(tx_id).is_some() {
+            TransactionOutcome::InvalidTransition
+        } else {
+            TransactionOutcome::Ignored
+        }
+    }
+
+    fn has_open_dispute(&self) -> bool {
+        self.transactions.values().any(|transaction| {
+            matches!(
+                transaction,
+                Transaction::Deposit(Deposit {
+                    state: TransactionState::Disputed,
+                    ..
+                })
+            )
+        })
+    }
+
+    fn check_new_transaction(&self, tx_id: TransactionId) -> Result<(), TransactionOutcome> {
+        if self.locked {
+            return Err(TransactionOutcome::SkippedLocked);
+        }
+        if self.transactions.contains_key(&tx_id) || self.undisputed_deposit(tx_id).is_ok() {
+            return Err(TransactionOutcome::SkippedDuplicate);
+        }
+        Ok(())
+    }
+
+    fn check_available(&self, available: Decimal) -> Result<(), TransactionOutcome> {
+        if self.policy.allows_available(available) {
+            Ok(())
+        } else {
+            Err(TransactionOutcome::SkippedBalanceBounds)
+        }
+    }
+}
+
+// Every balance change goes through here, so `available`, `held` and their sum always fit in a
+// `Decimal` and `Account::total` can't overflow.
+fn checked_balances(
+    available: Decimal,
+    held: Decimal,
+    available_delta: Decimal,
+    held_delta: Decimal,
+) -> Result<(Decimal, Decimal), TransactionOutcome> {
+    match (available.checked_add(available_delta), held.checked_add(held_delta)) {
+        (Some(available), Some(held)) if available.checked_add(held).is_some() => Ok((available, held)),
+        _ => Err(TransactionOutcome::SkippedBalanceOverflow),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    // Exercises the ledger directly, without the engine or any CSV/file I/O.
+    #[test]
+    fn ledger_processes_transactions_without_io_layer() {
+        let dec = |value| Decimal::from_str(value).unwrap();
+        let mut account = Account::default();
+
+        assert_eq!(account.deposit(1, dec("5.0")), TransactionOutcome::Applied);
+        assert_eq!(account.deposit(1, dec("5.0")), TransactionOutcome::SkippedDuplicate);
+        assert_eq!(account.withdraw(2, dec("1.5")), TransactionOutcome::Applied);
+        assert_eq!(
+            account.withdraw(3, dec("10.0")),
+            TransactionOutcome::SkippedInsufficientFunds
+        );
+        assert_eq!(account.dispute(1), TransactionOutcome::Applied);
+        assert_eq!(account.available(), dec("-1.5"));
+        assert_eq!(account.held(), dec("5.0"));
+
+        assert_eq!(account.chargeback(1), TransactionOutcome::Applied);
+        assert_eq!(account.total(), dec("-1.5"));
+        assert!(account.is_locked());
+        assert_eq!(account.deposit(4, dec("1.0")), TransactionOutcome::SkippedLocked);
+    }
+
+    #[test]
+    fn disputed_deposit_is_promoted_out_of_compact_storage() {
+        let dec = |value| Decimal::from_str(value).unwrap();
+        let mut account = Account::default();
+        for tx_id in [3, 1, 2] {
+            assert_eq!(account.deposit(tx_id, dec("1.5")), TransactionOutcome::Applied);
+        }
+        assert_eq!(account.deposits, [(1, dec("1.5")), (2, dec("1.5")), (3, dec("1.5"))]);
+        assert_eq!(account.resolve(2), TransactionOutcome::InvalidTransition);
+
+        assert_eq!(account.dispute(2), TransactionOutcome::Applied);
+        assert_eq!(account.deposits, [(1, dec("1.5")), (3, dec("1.5"))]);
+        assert!(matches!(account.deposit_state(2), Some(TransactionState::Disputed)));
+        assert_eq!(account.held(), dec("1.5"));
+        assert_eq!(account.deposit(2, dec("1.0")), TransactionOutcome::SkippedDuplicate);
+        assert_eq!(account.dispute(2), TransactionOutcome::InvalidTransition);
+
+        assert_eq!(account.resolve(2), TransactionOutcome::Applied);
+        assert_eq!(account.available(), dec("4.5"));
+        assert_eq!(account.transaction_count(), 3);
+    }
+
+    #[test]
+    fn balance_overflow_is_skipped() {
+        let half = Decimal::MAX / Decimal::TWO + Decimal::ONE;
+        let mut account = Account::default();
+
+        assert_eq!(account.deposit(1, half), TransactionOutcome::Applied);
+        assert_eq!(account.dispute(1), TransactionOutcome::Applied);
+        assert_eq!(account.deposit(2, half), TransactionOutcome::SkippedBalanceOverflow);
+        assert_eq!(
+            account.adjust(3, Decimal::MAX),
+            TransactionOutcome::SkippedBalanceOverflow
+        );
+        assert_eq!(account.total(), half);
+    }
+}
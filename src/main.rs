@@ -1,13 +1,37 @@
-use std::{io, path::PathBuf};
+use std::{fs::File, io, path::PathBuf};
 
 use clap::Parser;
-use color_eyre::Result;
-use payments_engine::Engine;
+use color_eyre::{eyre::bail, Result};
+use payments_engine::{Engine, ProcessingSummary, RowError};
+use rust_decimal::Decimal;
+use serde::Serialize;
 
 #[derive(Debug, Parser)]
 #[command(about = "Payment engine that tracks and emits account balances from an input transaction stream")]
 struct Cli {
     input_transactions_file: PathBuf,
+    /// Also write a JSON summary of the run (counts, locked accounts, system total) to this path.
+    #[arg(long, value_name = "PATH")]
+    json_summary: Option<PathBuf>,
+    /// Exit with an error after writing the accounts if any of them is locked.
+    #[arg(long)]
+    fail_on_locked: bool,
+    /// Only check the input: print the summary to stderr instead of writing accounts, and exit with
+    /// an error if any row is invalid. Dispute steps are checked with strict state transitions.
+    #[arg(long, conflicts_with_all = ["json_summary", "fail_on_locked"])]
+    validate_only: bool,
+    /// Also write every row that wasn't applied (line, reason, row) as CSV to this path.
+    #[arg(long, value_name = "PATH")]
+    errors: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonSummary<'a> {
+    #[serde(flatten)]
+    counts: &'a ProcessingSummary,
+    accounts: usize,
+    locked_accounts: usize,
+    system_total: Decimal,
 }
 
 fn main() -> Result<()> {
@@ -19,8 +43,48 @@ fn main() -> Result<()> {
         .init();
 
     let cli = Cli::parse();
-    let mut engine = Engine::new();
-    engine.apply_transactions_from_file(cli.input_transactions_file)?;
+    let mut engine = Engine::new().with_strict_state_transitions(cli.validate_only);
+    if let Some(path) = &cli.errors {
+        let row_errors = engine.apply_transactions_from_file_collecting(&cli.input_transactions_file)?;
+        write_row_errors(File::create(path)?, &row_errors)?;
+    } else {
+        engine.apply_transactions_from_file(&cli.input_transactions_file)?;
+    }
+    if cli.validate_only {
+        serde_json::to_writer_pretty(io::stderr(), engine.summary())?;
+        eprintln!();
+        let invalid = engine.summary().invalid_rows();
+        if invalid > 0 {
+            bail!("{invalid} invalid rows");
+        }
+        return Ok(());
+    }
+    let engine = engine.finalize();
     engine.write_accounts(io::stdout())?;
+
+    if let Some(path) = cli.json_summary {
+        let summary = JsonSummary {
+            counts: engine.summary(),
+            accounts: engine.account_count(),
+            locked_accounts: engine.locked_accounts().len(),
+            system_total: engine.system_total(),
+        };
+        serde_json::to_writer_pretty(File::create(path)?, &summary)?;
+    }
+    if cli.fail_on_locked {
+        if let Err(locked) = engine.assert_no_locked() {
+            bail!("locked accounts: {locked:?}");
+        }
+    }
+    Ok(())
+}
+
+fn write_row_errors(writer: impl io::Write, row_errors: &[RowError]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["line", "reason", "row"])?;
+    for row_error in row_errors {
+        writer.write_record([&row_error.line.to_string(), &row_error.reason, &row_error.row])?;
+    }
+    writer.flush()?;
     Ok(())
 }
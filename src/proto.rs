@@ -0,0 +1,23 @@
+//! Protocol Buffers messages for account state, see [`Engine::to_proto`](crate::Engine::to_proto).
+//!
+//! The messages are written by hand to match `proto/accounts.proto` rather than generated at
+//! build time, so building the crate doesn't need `protoc`. Keep the two in sync.
+
+/// One client account. Balances are decimal strings rounded to the engine's configured
+/// [`ColumnPrecision`](crate::ColumnPrecision), exactly as in the CSV output.
+#[derive(Clone, PartialEq, Eq, prost::Message)]
+pub struct AccountMessage {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+    #[prost(string, tag = "2")]
+    pub available: String,
+    #[prost(string, tag = "3")]
+    pub held: String,
+    #[prost(string, tag = "4")]
+    pub total: String,
+    #[prost(bool, tag = "5")]
+    pub locked: bool,
+    /// Only set for accounts that adopted a currency from multi-currency input.
+    #[prost(string, optional, tag = "6")]
+    pub currency: Option<String>,
+}
@@ -0,0 +1,221 @@
+use serde::Serialize;
+
+use crate::TransactionOutcome;
+
+/// Running counts of how the engine handled its input.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ProcessingSummary {
+    pub applied: usize,
+    pub skipped_locked: usize,
+    pub skipped_duplicate: usize,
+    pub skipped_insufficient_funds: usize,
+    pub skipped_invalid_amount: usize,
+    pub skipped_balance_bounds: usize,
+    /// Transactions that would have overflowed an account balance.
+    pub skipped_balance_overflow: usize,
+    /// Withdrawals held back by an open dispute on the account.
+    pub skipped_open_dispute: usize,
+    /// Transactions in a different currency from their account.
+    pub skipped_currency_mismatch: usize,
+    /// Transactions for a reserved client id.
+    pub skipped_reserved_client: usize,
+    /// Transactions for a new client once the client limit was reached.
+    pub skipped_client_limit: usize,
+    /// Withdrawals over the withdrawal velocity limit.
+    pub skipped_velocity_limit: usize,
+    pub ignored: usize,
+    /// Dispute lifecycle steps from the wrong state. Only counted with strict state transitions,
+    /// otherwise they're part of `ignored`.
+    pub invalid_transitions: usize,
+    /// Dispute lifecycle steps naming a client that never existed. Only counted with strict state
+    /// transitions, otherwise they're part of `ignored`.
+    pub dispute_unknown_client: usize,
+    /// Deposits reusing the id of a charged-back deposit. Only counted with strict state
+    /// transitions, otherwise they're just part of `skipped_duplicate`.
+    pub charged_back_id_reuse: usize,
+    /// Disputes, resolves or chargebacks against a deposit that was already charged back.
+    pub post_chargeback_attempts: usize,
+    /// Disputes of a deposit that was already under dispute, such as two dispute rows racing in
+    /// from concurrent ingestion. The repeat is otherwise ignored.
+    pub duplicate_dispute_attempts: usize,
+    /// Disputes, resolves and chargebacks dropped as repeats of one seen within the coalescing
+    /// window. Only counted with dispute coalescing enabled.
+    pub coalesced_dispute_steps: usize,
+    /// Resolves and chargebacks of a deposit that wasn't disputed yet. Only counted with lifecycle
+    /// order tracking.
+    pub out_of_order_lifecycle: usize,
+    /// Deposits still disputed at the end of an input. Only counted when disputes are required to
+    /// close, without strict state transitions.
+    pub open_disputes_at_end: usize,
+    /// Disputes resolved when the engine was finalized because they had been open too long.
+    pub auto_resolved_disputes: usize,
+    /// CSV rows that couldn't be parsed into a transaction.
+    pub malformed_rows: usize,
+    /// Applied transactions that couldn't be written to the audit sink.
+    pub audit_write_errors: usize,
+    /// Wall-clock nanoseconds spent reading and parsing rows. Only measured with timing enabled.
+    pub parse_nanos: u64,
+    /// Wall-clock nanoseconds spent applying parsed rows. Only measured with timing enabled.
+    pub apply_nanos: u64,
+}
+
+impl ProcessingSummary {
+    /// Rows that were wrong in themselves rather than just skipped by the current balances:
+    /// malformed rows plus the dispute lifecycle steps flagged by strict state transitions.
+    #[must_use]
+    pub const fn invalid_rows(&self) -> usize {
+        self.malformed_rows + self.invalid_transitions + self.dispute_unknown_client
+    }
+
+    pub(crate) const fn record(&mut self, outcome: TransactionOutcome) {
+        let counter = match outcome {
+            TransactionOutcome::Applied => &mut self.applied,
+            TransactionOutcome::SkippedLocked => &mut self.skipped_locked,
+            TransactionOutcome::SkippedDuplicate => &mut self.skipped_duplicate,
+            TransactionOutcome::SkippedInsufficientFunds => &mut self.skipped_insufficient_funds,
+            TransactionOutcome::SkippedInvalidAmount => &mut self.skipped_invalid_amount,
+            TransactionOutcome::SkippedBalanceBounds => &mut self.skipped_balance_bounds,
+            TransactionOutcome::SkippedBalanceOverflow => &mut self.skipped_balance_overflow,
+            TransactionOutcome::SkippedOpenDispute => &mut self.skipped_open_dispute,
+            TransactionOutcome::SkippedCurrencyMismatch => &mut self.skipped_currency_mismatch,
+            TransactionOutcome::SkippedReservedClient => &mut self.skipped_reserved_client,
+            TransactionOutcome::SkippedClientLimit => &mut self.skipped_client_limit,
+            TransactionOutcome::SkippedVelocityLimit => &mut self.skipped_velocity_limit,
+            TransactionOutcome::Ignored => &mut self.ignored,
+            TransactionOutcome::InvalidTransition => &mut self.invalid_transitions,
+            TransactionOutcome::UnknownClient => &mut self.dispute_unknown_client,
+        };
+        *counter += 1;
+    }
+
+    // Adds another run's counts to these, such as one shard's of a sharded run. Destructured so a
+    // new counter can't be forgotten here.
+    pub(crate) const fn absorb(&mut self, other: &Self) {
+        let Self {
+            applied,
+            skipped_locked,
+            skipped_duplicate,
+            skipped_insufficient_funds,
+            skipped_invalid_amount,
+            skipped_balance_bounds,
+            skipped_balance_overflow,
+            skipped_open_dispute,
+            skipped_currency_mismatch,
+            skipped_reserved_client,
+            skipped_client_limit,
+            skipped_velocity_limit,
+            ignored,
+            invalid_transitions,
+            dispute_unknown_client,
+            charged_back_id_reuse,
+            post_chargeback_attempts,
+            duplicate_dispute_attempts,
+            coalesced_dispute_steps,
+            out_of_order_lifecycle,
+            open_disputes_at_end,
+            auto_resolved_disputes,
+            malformed_rows,
+            audit_write_errors,
+            parse_nanos,
+            apply_nanos,
+        } = other;
+        self.applied += *applied;
+        self.skipped_locked += *skipped_locked;
+        self.skipped_duplicate += *skipped_duplicate;
+        self.skipped_insufficient_funds += *skipped_insufficient_funds;
+        self.skipped_invalid_amount += *skipped_invalid_amount;
+        self.skipped_balance_bounds += *skipped_balance_bounds;
+        self.skipped_balance_overflow += *skipped_balance_overflow;
+        self.skipped_open_dispute += *skipped_open_dispute;
+        self.skipped_currency_mismatch += *skipped_currency_mismatch;
+        self.skipped_reserved_client += *skipped_reserved_client;
+        self.skipped_client_limit += *skipped_client_limit;
+        self.skipped_velocity_limit += *skipped_velocity_limit;
+        self.ignored += *ignored;
+        self.invalid_transitions += *invalid_transitions;
+        self.dispute_unknown_client += *dispute_unknown_client;
+        self.charged_back_id_reuse += *charged_back_id_reuse;
+        self.post_chargeback_attempts += *post_chargeback_attempts;
+        self.duplicate_dispute_attempts += *duplicate_dispute_attempts;
+        self.coalesced_dispute_steps += *coalesced_dispute_steps;
+        self.out_of_order_lifecycle += *out_of_order_lifecycle;
+        self.open_disputes_at_end += *open_disputes_at_end;
+        self.auto_resolved_disputes += *auto_resolved_disputes;
+        self.malformed_rows += *malformed_rows;
+        self.audit_write_errors += *audit_write_errors;
+        self.parse_nanos = self.parse_nanos.saturating_add(*parse_nanos);
+        self.apply_nanos = self.apply_nanos.saturating_add(*apply_nanos);
+    }
+
+    // Takes back counts from `other`, such as the ones an undone transaction added. Destructured
+    // for the same reason as `absorb`.
+    pub(crate) const fn retract(&mut self, other: &Self) {
+        let Self {
+            applied,
+            skipped_locked,
+            skipped_duplicate,
+            skipped_insufficient_funds,
+            skipped_invalid_amount,
+            skipped_balance_bounds,
+            skipped_balance_overflow,
+            skipped_open_dispute,
+            skipped_currency_mismatch,
+            skipped_reserved_client,
+            skipped_client_limit,
+            skipped_velocity_limit,
+            ignored,
+            invalid_transitions,
+            dispute_unknown_client,
+            charged_back_id_reuse,
+            post_chargeback_attempts,
+            duplicate_dispute_attempts,
+            coalesced_dispute_steps,
+            out_of_order_lifecycle,
+            open_disputes_at_end,
+            auto_resolved_disputes,
+            malformed_rows,
+            audit_write_errors,
+            parse_nanos,
+            apply_nanos,
+        } = other;
+        self.applied = self.applied.saturating_sub(*applied);
+        self.skipped_locked = self.skipped_locked.saturating_sub(*skipped_locked);
+        self.skipped_duplicate = self.skipped_duplicate.saturating_sub(*skipped_duplicate);
+        self.skipped_insufficient_funds = self
+            .skipped_insufficient_funds
+            .saturating_sub(*skipped_insufficient_funds);
+        self.skipped_invalid_amount = self.skipped_invalid_amount.saturating_sub(*skipped_invalid_amount);
+        self.skipped_balance_bounds = self.skipped_balance_bounds.saturating_sub(*skipped_balance_bounds);
+        self.skipped_balance_overflow = self.skipped_balance_overflow.saturating_sub(*skipped_balance_overflow);
+        self.skipped_open_dispute = self.skipped_open_dispute.saturating_sub(*skipped_open_dispute);
+        self.skipped_currency_mismatch = self
+            .skipped_currency_mismatch
+            .saturating_sub(*skipped_currency_mismatch);
+        self.skipped_reserved_client = self.skipped_reserved_client.saturating_sub(*skipped_reserved_client);
+        self.skipped_client_limit = self.skipped_client_limit.saturating_sub(*skipped_client_limit);
+        self.skipped_velocity_limit = self.skipped_velocity_limit.saturating_sub(*skipped_velocity_limit);
+        self.ignored = self.ignored.saturating_sub(*ignored);
+        self.invalid_transitions = self.invalid_transitions.saturating_sub(*invalid_transitions);
+        self.dispute_unknown_client = self.dispute_unknown_client.saturating_sub(*dispute_unknown_client);
+        self.charged_back_id_reuse = self.charged_back_id_reuse.saturating_sub(*charged_back_id_reuse);
+        self.post_chargeback_attempts = self.post_chargeback_attempts.saturating_sub(*post_chargeback_attempts);
+        self.duplicate_dispute_attempts = self
+            .duplicate_dispute_attempts
+            .saturating_sub(*duplicate_dispute_attempts);
+        self.coalesced_dispute_steps = self.coalesced_dispute_steps.saturating_sub(*coalesced_dispute_steps);
+        self.out_of_order_lifecycle = self.out_of_order_lifecycle.saturating_sub(*out_of_order_lifecycle);
+        self.open_disputes_at_end = self.open_disputes_at_end.saturating_sub(*open_disputes_at_end);
+        self.auto_resolved_disputes = self.auto_resolved_disputes.saturating_sub(*auto_resolved_disputes);
+        self.malformed_rows = self.malformed_rows.saturating_sub(*malformed_rows);
+        self.audit_write_errors = self.audit_write_errors.saturating_sub(*audit_write_errors);
+        self.parse_nanos = self.parse_nanos.saturating_sub(*parse_nanos);
+        self.apply_nanos = self.apply_nanos.saturating_sub(*apply_nanos);
+    }
+
+    // Counts added between `before` and these.
+    pub(crate) fn since(&self, before: &Self) -> Self {
+        let mut since = self.clone();
+        since.retract(before);
+        since
+    }
+}
@@ -0,0 +1,30 @@
+//! Browser entry point. Everything here is a thin string-in, string-out wrapper around [`Engine`].
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::Engine;
+
+/// Processes a CSV of transactions and returns the resulting accounts as CSV, or the error message
+/// if the input couldn't be read.
+///
+/// # Errors
+///
+/// Returns the displayed [`crate::EngineError`] if the input can't be read.
+#[wasm_bindgen]
+pub fn process_csv(input: &str) -> Result<String, String> {
+    Engine::process_str(input).map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn processes_csv_string_to_string() {
+        let input = "type,client,tx,amount\ndeposit,2,1,2.0\ndeposit,1,2,1.5\nwithdrawal,1,3,0.5\n";
+        assert_eq!(
+            process_csv(input).unwrap(),
+            "client,available,held,total,locked\n1,1,0,1,false\n2,2,0,2,false\n"
+        );
+    }
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, fs, process::Command, str::FromStr};
 
-use payments_engine::Engine;
+use payments_engine::{AggregateStats, Engine};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
@@ -29,6 +29,307 @@ fn processes_sample_transactions_end_to_end() {
     assert_eq!(actual_accounts, expected_accounts());
 }
 
+#[test]
+fn sample_underwater_accounts() {
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("engine accepts sample csv");
+
+    assert_eq!(engine.underwater_accounts(), [(2, dec("-250"))]);
+}
+
+#[test]
+fn sample_has_locked_accounts() {
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("engine accepts sample csv");
+
+    assert_eq!(engine.assert_no_locked(), Err(vec![2, 4]));
+    assert_eq!(Engine::new().assert_no_locked(), Ok(()));
+}
+
+#[test]
+fn sample_locked_accounts_only() {
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("engine accepts sample csv");
+
+    let mut output = Vec::new();
+    engine
+        .write_accounts_filtered(&mut output, |_, account| account.locked)
+        .expect("engine emits accounts");
+    let expected: HashMap<_, _> = expected_accounts()
+        .into_iter()
+        .filter(|(_, account)| account.locked)
+        .collect();
+    assert_eq!(expected.len(), 2);
+    assert_eq!(read_accounts(&output), expected);
+}
+
+#[test]
+fn sample_aggregate_stats() {
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("engine accepts sample csv");
+
+    assert_eq!(
+        engine.aggregate_stats(),
+        AggregateStats {
+            accounts: 5,
+            locked_accounts: 2,
+            total_available: dec("-491.5"),
+            total_held: dec("273.1234"),
+            system_total: dec("-218.3766"),
+            negative_accounts: 1,
+            open_disputes: 3,
+        }
+    );
+}
+
+#[test]
+fn seeded_engine_continues_from_previous_output() {
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("engine accepts sample csv");
+    let mut previous_output = Vec::new();
+    engine
+        .write_accounts(&mut previous_output)
+        .expect("engine emits accounts");
+
+    // The sample leaves client 2 with funds held by an open dispute.
+    let mut next_batch = Engine::new().with_seeded_held(true);
+    next_batch
+        .seed_from_accounts_csv(previous_output.as_slice())
+        .expect("engine accepts previous output");
+    next_batch
+        .apply_transactions("type,client,tx,amount\ndeposit,1,100,1.0\ndeposit,2,101,1.0\n".as_bytes())
+        .expect("engine accepts next batch");
+    let mut output = Vec::new();
+    next_batch.write_accounts(&mut output).expect("engine emits accounts");
+
+    let mut expected = expected_accounts();
+    expected.insert(1, account(1, "7.5", "0", "7.5", false));
+    assert_eq!(read_accounts(&output), expected);
+}
+
+#[test]
+fn binary_writes_json_summary() {
+    let summary_path = std::env::temp_dir().join(format!("payments_engine_summary_{}.json", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/sample_transactions.csv"))
+        .arg("--json-summary")
+        .arg(&summary_path)
+        .output()
+        .expect("binary runs");
+    assert!(output.status.success());
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&summary_path).expect("summary written")).expect("summary is json");
+    fs::remove_file(&summary_path).expect("summary removed");
+    assert_eq!(summary["locked_accounts"], 2);
+    assert_eq!(summary["accounts"], expected_accounts().len());
+}
+
+#[test]
+fn binary_validate_only_rejects_bad_rows() {
+    let input_path = std::env::temp_dir().join(format!("payments_engine_invalid_{}.csv", std::process::id()));
+    fs::write(
+        &input_path,
+        "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,two,1.0\n",
+    )
+    .expect("input written");
+    let output = Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg(&input_path)
+        .arg("--validate-only")
+        .output()
+        .expect("binary runs");
+    fs::remove_file(&input_path).expect("input removed");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\"malformed_rows\": 1"));
+}
+
+#[test]
+fn binary_writes_skipped_rows_to_errors_file() {
+    let input_path = std::env::temp_dir().join(format!("payments_engine_errors_input_{}.csv", std::process::id()));
+    let errors_path = std::env::temp_dir().join(format!("payments_engine_errors_{}.csv", std::process::id()));
+    fs::write(
+        &input_path,
+        "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,two,1.0\nwithdrawal,1,3,5.0\n",
+    )
+    .expect("input written");
+    let output = Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg(&input_path)
+        .arg("--errors")
+        .arg(&errors_path)
+        .output()
+        .expect("binary runs");
+    fs::remove_file(&input_path).expect("input removed");
+    assert!(output.status.success());
+
+    let errors = fs::read_to_string(&errors_path).expect("errors written");
+    fs::remove_file(&errors_path).expect("errors removed");
+    let mut reader = csv::Reader::from_reader(errors.as_bytes());
+    let rows: Vec<_> = reader.records().map(|row| row.expect("error row reads")).collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!((&rows[0][0], &rows[0][2]), ("3", "deposit,1,two,1.0"));
+    assert_eq!((&rows[1][0], &rows[1][2]), ("4", "withdrawal,1,3,5.0"));
+
+    let mut expected = HashMap::new();
+    expected.insert(1, account(1, "1", "0", "1", false));
+    assert_eq!(read_accounts(&output.stdout), expected);
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn bzip2_sample_matches_plain() {
+    use std::io::Write;
+
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder
+        .write_all(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("sample compresses");
+    let compressed = encoder.finish().expect("sample compresses");
+    assert_eq!(
+        accounts_from_compressed_file("csv.bz2", &compressed),
+        expected_accounts()
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_sample_matches_plain() {
+    let compressed = zstd::encode_all(SAMPLE_TRANSACTIONS.as_bytes(), 0).expect("sample compresses");
+    assert_eq!(
+        accounts_from_compressed_file("csv.zst", &compressed),
+        expected_accounts()
+    );
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_sample_matches_buffered() {
+    let path = std::env::temp_dir().join(format!("payments_engine_mmap_{}.csv", std::process::id()));
+    fs::write(&path, SAMPLE_TRANSACTIONS).expect("sample written");
+    let mut mapped = Engine::new();
+    let result = mapped.apply_transactions_from_mmap(&path);
+    let mut buffered = Engine::new();
+    buffered
+        .apply_transactions_from_file(&path)
+        .expect("engine accepts sample");
+    fs::remove_file(&path).expect("sample removed");
+    result.expect("engine accepts mapped sample");
+
+    let mut output = Vec::new();
+    mapped.write_accounts(&mut output).expect("engine emits accounts");
+    assert_eq!(read_accounts(&output), expected_accounts());
+    assert_eq!(mapped.summary(), buffered.summary());
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_output_round_trips_to_csv_rows() {
+    use arrow_array::{cast::AsArray, types::Decimal128Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("engine accepts sample csv");
+    let path = std::env::temp_dir().join(format!("payments_engine_accounts_{}.parquet", std::process::id()));
+    engine
+        .write_accounts_parquet(fs::File::create(&path).expect("parquet file created"))
+        .expect("engine emits parquet");
+    let file = fs::File::open(&path).expect("parquet file opened");
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(ParquetRecordBatchReaderBuilder::build)
+        .expect("parquet file readable");
+    fs::remove_file(&path).expect("parquet file removed");
+
+    let mut accounts = HashMap::new();
+    for batch in reader {
+        let batch = batch.expect("record batch decodes");
+        let decimal = |column: usize, row: usize| {
+            let array = batch.column(column).as_primitive::<Decimal128Type>();
+            let scale = u32::try_from(array.scale()).expect("non-negative scale");
+            Decimal::from_i128_with_scale(array.value(row), scale)
+        };
+        let clients = batch.column(0).as_primitive::<arrow_array::types::UInt16Type>();
+        let locked = batch.column(4).as_boolean();
+        for row in 0..batch.num_rows() {
+            let client = clients.value(row);
+            let account = AccountRow {
+                client,
+                available: decimal(1, row),
+                held: decimal(2, row),
+                total: decimal(3, row),
+                locked: locked.value(row),
+            };
+            accounts.insert(client, account);
+        }
+    }
+
+    let mut output = Vec::new();
+    engine.write_accounts(&mut output).expect("engine emits accounts");
+    assert_eq!(accounts, read_accounts(&output));
+}
+
+#[cfg(feature = "prost")]
+#[test]
+fn proto_stream_round_trips_to_csv_rows() {
+    use payments_engine::proto::AccountMessage;
+    use prost::Message;
+
+    let mut engine = Engine::new();
+    engine
+        .apply_transactions(SAMPLE_TRANSACTIONS.as_bytes())
+        .expect("engine accepts sample csv");
+    let mut stream = Vec::new();
+    engine.write_accounts_proto(&mut stream).expect("engine emits messages");
+
+    let mut remaining = stream.as_slice();
+    let mut accounts = HashMap::new();
+    while !remaining.is_empty() {
+        let message = AccountMessage::decode_length_delimited(&mut remaining).expect("message decodes");
+        let client = u16::try_from(message.client).expect("client id fits u16");
+        assert_eq!(message.currency, None);
+        accounts.insert(
+            client,
+            account(
+                client,
+                &message.available,
+                &message.held,
+                &message.total,
+                message.locked,
+            ),
+        );
+    }
+    assert_eq!(engine.to_proto().len(), accounts.len());
+
+    let mut output = Vec::new();
+    engine.write_accounts(&mut output).expect("engine emits accounts");
+    assert_eq!(accounts, read_accounts(&output));
+}
+
+#[cfg(any(feature = "bzip2", feature = "zstd"))]
+fn accounts_from_compressed_file(extension: &str, compressed: &[u8]) -> HashMap<u16, AccountRow> {
+    let path = std::env::temp_dir().join(format!("payments_engine_sample_{}.{extension}", std::process::id()));
+    fs::write(&path, compressed).expect("compressed sample written");
+    let mut engine = Engine::new();
+    let result = engine.apply_transactions_from_file(&path);
+    fs::remove_file(&path).expect("compressed sample removed");
+    result.expect("engine accepts compressed sample");
+
+    let mut output = Vec::new();
+    engine.write_accounts(&mut output).expect("engine emits accounts");
+    read_accounts(&output)
+}
+
 fn dec(value: &str) -> Decimal {
     Decimal::from_str(value).expect("literal decimal parses")
 }
@@ -0,0 +1,66 @@
+//! Property tests feeding arbitrary input to the engine. Whatever the input, processing must return
+//! `Ok` or `Err` and never panic.
+
+use payments_engine::Engine;
+use proptest::prelude::*;
+
+// Amounts at and around the edges of `Decimal`'s range, plus strings that shouldn't parse.
+const AMOUNTS: &[&str] = &[
+    "",
+    "0",
+    "1.5",
+    "-1.5",
+    "0.0000000000000000000000000001",
+    "79228162514264337593543950335",
+    "-79228162514264337593543950335",
+    "7922816251426433759354395033.5",
+    "0.00001",
+    "79228162514264337593543950336",
+    "50000000000000000000000000000.5",
+    "-50000000000000000000000000000.5",
+    "1e10",
+    "NaN",
+    "abc",
+];
+const KINDS: &[&str] = &[
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "adjustment",
+    "unknown",
+];
+
+fn row() -> impl Strategy<Value = String> {
+    (
+        prop::sample::select(KINDS),
+        0..4u32,
+        0..8u32,
+        prop::sample::select(AMOUNTS),
+    )
+        .prop_map(|(kind, client, tx, amount)| format!("{kind},{client},{tx},{amount}\n"))
+}
+
+fn run(input: &[u8]) {
+    let mut engine = Engine::new();
+    let _ = engine.apply_transactions(input);
+    let _ = format!("{engine:?}");
+    let _ = engine.underwater_accounts();
+    let _ = engine.held_ratio(1);
+    let _ = engine.client_stats(1);
+    let _ = engine.take_deltas();
+    let _ = engine.write_accounts(Vec::new());
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_bytes_never_panic(input in prop::collection::vec(any::<u8>(), 0..512)) {
+        run(&input);
+    }
+
+    #[test]
+    fn arbitrary_rows_never_panic(rows in prop::collection::vec(row(), 0..64)) {
+        run(format!("type,client,tx,amount\n{}", rows.concat()).as_bytes());
+    }
+}